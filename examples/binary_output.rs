@@ -0,0 +1,16 @@
+use std::io::Write;
+
+// writes output that is not clean utf-8 text to both stdout and stderr: invalid bytes, windows
+// line ending, very long line and final line without newline
+fn main() {
+    let mut output: Vec<u8> = Vec::new();
+    output.extend_from_slice(b"plain line\n");
+    output.extend_from_slice(b"invalid \xff\xfe bytes\n");
+    output.extend_from_slice(b"windows line\r\n");
+    output.resize(output.len() + 100_000, b'x');
+    output.push(b'\n');
+    output.extend_from_slice(b"partial line");
+
+    std::io::stdout().write_all(&output).unwrap();
+    std::io::stderr().write_all(&output).unwrap();
+}
@@ -1,5 +1,6 @@
 use std::{thread::sleep, time::Duration};
 
+#[allow(clippy::never_loop)]
 fn main() {
     loop {
         sleep(Duration::from_secs(10));
@@ -4,7 +4,7 @@ use std::{thread::sleep, time::Duration};
 fn main() {
     let choices = [true, false];
     let weights = [9, 1];
-    let dist = WeightedIndex::new(&weights).unwrap();
+    let dist = WeightedIndex::new(weights).unwrap();
     let mut rng = thread_rng();
 
     sleep(Duration::from_secs(10));
@@ -12,6 +12,5 @@ fn main() {
         panic!("This application crashed.");
     } else {
         println!("All work done, closing.");
-        return;
     }
 }
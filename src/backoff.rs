@@ -0,0 +1,132 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use crate::command_config::RestartPolicy;
+
+// tracks delay before next restart of a command
+pub(crate) struct Backoff<'a> {
+    policy: &'a RestartPolicy,
+
+    // delay before next restart, without jitter
+    current: Duration,
+}
+
+impl<'a> Backoff<'a> {
+    // creates backoff starting at configured restart delay
+    pub(crate) fn new(policy: &'a RestartPolicy) -> Backoff<'a> {
+        Backoff {
+            policy,
+            current: policy.delay.to_std().unwrap_or(Duration::ZERO),
+        }
+    }
+
+    // delay before restarting process that was up for given time
+    pub(crate) fn next_delay(&mut self, uptime: Duration) -> Duration {
+        self.next_delay_with(uptime, random())
+    }
+
+    // delay before restart with given random number between 0 and 1 used for jitter. Process that
+    // stayed up for stable period starts again from configured restart delay
+    fn next_delay_with(&mut self, uptime: Duration, random: f64) -> Duration {
+        let max_delay = self.policy.max_delay.to_std().unwrap_or(Duration::ZERO);
+        if uptime >= self.policy.stable_period.to_std().unwrap_or(Duration::ZERO) {
+            self.current = self.policy.delay.to_std().unwrap_or(Duration::ZERO);
+        }
+        let delay = self.current.min(max_delay);
+        // grown delay is clamped before it becomes a duration, which could overflow otherwise
+        let grown = (delay.as_secs_f64() * self.policy.multiplier).min(max_delay.as_secs_f64());
+        self.current = Duration::try_from_secs_f64(grown).unwrap_or(max_delay);
+        // random in [0, 1) maps to factor in [1 - jitter, 1 + jitter)
+        delay.mul_f64(1f64 + self.policy.jitter * (2f64 * random - 1f64))
+    }
+}
+
+// random number between 0 and 1, good enough to spread restarts apart
+fn random() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn policy(jitter: f64) -> RestartPolicy {
+        RestartPolicy {
+            delay: chrono::Duration::seconds(1),
+            multiplier: 2f64,
+            max_delay: chrono::Duration::seconds(5),
+            jitter,
+            stable_period: chrono::Duration::seconds(60),
+        }
+    }
+
+    #[test]
+    fn test_next_delay() {
+        let policy = policy(0f64);
+        let mut backoff = Backoff::new(&policy);
+        let quick = Duration::from_secs(1);
+        let delays: Vec<u64> = (0..5)
+            .map(|_| backoff.next_delay_with(quick, 0.5).as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+
+        // stable process starts from restart delay again
+        assert_eq!(
+            backoff.next_delay_with(Duration::from_secs(60), 0.5),
+            Duration::from_secs(1)
+        );
+        assert_eq!(backoff.next_delay_with(quick, 0.5), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_huge_multiplier() {
+        let policy = RestartPolicy {
+            multiplier: 1e20,
+            ..policy(0f64)
+        };
+        let mut backoff = Backoff::new(&policy);
+        let quick = Duration::from_secs(1);
+        let delays: Vec<u64> = (0..3)
+            .map(|_| backoff.next_delay_with(quick, 0.5).as_secs())
+            .collect();
+        assert_eq!(delays, vec![1, 5, 5]);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let policy = policy(0.5);
+        let quick = Duration::from_secs(1);
+        assert_eq!(
+            Backoff::new(&policy).next_delay_with(quick, 0f64),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            Backoff::new(&policy).next_delay_with(quick, 0.5),
+            Duration::from_secs(1)
+        );
+        let delay = Backoff::new(&policy).next_delay(quick);
+        assert!(delay >= Duration::from_millis(500) && delay < Duration::from_millis(1500));
+    }
+}
@@ -17,14 +17,61 @@ along with this program. If not, see <http://www.gnu.org/licenses/>.
 
 use std::{num::TryFromIntError, path::Path};
 
+use regex::Regex;
+
 use crate::config_error::ConfigError;
 
 // default number of lines to store for stdout history
 const DEFAULT_HISTORY: usize = 1000usize;
 
+// default number of lines kept in interface tab of a command
+const DEFAULT_SCROLLBACK: usize = 1000usize;
+
 // default mode for application if none specified
 const DEFAULT_MODE: CommandMode = CommandMode::RunUntilSuccess;
 
+// default signal sent to process when runner shuts down
+const DEFAULT_STOP_SIGNAL: i32 = libc::SIGTERM;
+
+// default time process has to exit after receiving stop signal, before it is killed
+const DEFAULT_STOP_TIMEOUT_S: i64 = 10i64;
+
+// default time between readiness checks
+const DEFAULT_READY_INTERVAL_S: i64 = 1i64;
+
+// default time process has to become ready
+const DEFAULT_READY_TIMEOUT_S: i64 = 60i64;
+
+// default time between health checks
+const DEFAULT_HEALTH_INTERVAL_S: i64 = 10i64;
+
+// default time single health check has to pass
+const DEFAULT_HEALTH_TIMEOUT_S: i64 = 5i64;
+
+// default number of consecutive failed health checks before process is restarted
+const DEFAULT_HEALTH_FAILURES: u32 = 3u32;
+
+// default factor restart delay grows by after each quick restart
+const DEFAULT_BACKOFF_MULTIPLIER: f64 = 2f64;
+
+// largest factor restart delay can grow by, anything more reaches max delay right away anyway
+const MAX_BACKOFF_MULTIPLIER: f64 = 100f64;
+
+// default longest delay between restarts
+const DEFAULT_MAX_DELAY_S: i64 = 300i64;
+
+// default time process has to stay up for restart delay to reset
+const DEFAULT_STABLE_PERIOD_S: i64 = 60i64;
+
+// default number of rotated log files kept
+const DEFAULT_LOG_KEEP: usize = 5usize;
+
+// default time cpu usage has to stay over its limit before process is restarted
+const DEFAULT_MAX_CPU_FOR_S: i64 = 60i64;
+
+// default io priority within io scheduling class
+const DEFAULT_IONICE_LEVEL: u64 = 4u64;
+
 // enum indicating whether app should be restarted
 #[derive(Debug, Clone)]
 pub(crate) enum CommandMode {
@@ -46,7 +93,7 @@ pub(crate) enum CommandMode {
 
 // single app configuration
 // this struct holds all information needed to successfully run a process
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct CommandConfig {
     // command to execute
     pub(crate) command: String,
@@ -57,21 +104,266 @@ pub(crate) struct CommandConfig {
     // number of lines to store for stdout
     pub(crate) stdout_history: usize,
 
+    // number of lines kept in interface tab
+    pub(crate) scrollback: usize,
+
     // mode to run application in
     pub(crate) mode: CommandMode,
 
     // name given to application
     pub(crate) name: String,
 
-    pub(crate) backup_strategy: Option<BackupStrategy>,
+    // escalation stages used when process keeps crashing, in order
+    pub(crate) backup_strategy: Vec<BackupStrategy>,
+
+    // signal sent to process to ask it to stop
+    pub(crate) stop_signal: i32,
+
+    // time given to process to exit after stop signal before it is killed
+    pub(crate) stop_timeout: chrono::Duration,
+
+    // working directory of the process. Runner's working directory is used if none
+    pub(crate) cwd: Option<String>,
+
+    // environment variables set for the process, from env file first and env after it
+    pub(crate) env: Vec<(String, String)>,
+
+    // whether process starts with only variables from env instead of inheriting runner's environment
+    pub(crate) clear_env: bool,
+
+    // names of commands that must be satisfied before this command is started
+    pub(crate) depends_on: Vec<String>,
+
+    // checks that tell when started process is ready to be used
+    pub(crate) ready_when: Option<ReadyWhen>,
+
+    // checks that tell whether ready process still works
+    pub(crate) health_checks: Vec<HealthCheck>,
+
+    // delays between restarts
+    pub(crate) restart: RestartPolicy,
+
+    // limits on crash folders of this command
+    pub(crate) crash_retention: CrashRetention,
+
+    // continuous log of process output, kept regardless of exit status
+    pub(crate) log: Option<LogConfig>,
+
+    // limits on resources used by the process, enforced by restarting it
+    pub(crate) limits: ResourceLimits,
+
+    // limits, priority and umask applied to the process before it executes the command
+    pub(crate) process: ProcessSettings,
+}
+
+// action taken when process does not become ready in time
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ReadyFailure {
+    // kill process and handle it as a crash
+    Restart,
+
+    // stop command until it is started through control socket
+    Stop,
+
+    // treat process as ready anyway
+    Ignore,
+}
+
+// readiness checks. Process is ready once all configured checks pass
+#[derive(Debug, Clone)]
+pub(crate) struct ReadyWhen {
+    // local tcp port accepting connections
+    pub(crate) tcp_port: Option<u16>,
+
+    // file that has to exist
+    pub(crate) file: Option<String>,
+
+    // pattern stdout line has to match
+    pub(crate) log_pattern: Option<Regex>,
+
+    // probe command and its arguments that has to exit with 0
+    pub(crate) command: Option<Vec<String>>,
+
+    // time between checks
+    pub(crate) interval: chrono::Duration,
+
+    // time process has to become ready
+    pub(crate) timeout: chrono::Duration,
+
+    // what to do when process does not become ready in time
+    pub(crate) on_failure: ReadyFailure,
+}
+
+// what liveness check probes
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum HealthProbe {
+    // probe command and its arguments that has to exit with 0
+    Command(Vec<String>),
+
+    // local tcp port accepting connections
+    TcpPort(u16),
+
+    // longest time process may go without writing a line to stdout
+    StdoutSilence(chrono::Duration),
+}
+
+// periodic liveness check. Process is restarted after given number of consecutive failures
+#[derive(Debug, Clone)]
+pub(crate) struct HealthCheck {
+    pub(crate) probe: HealthProbe,
+
+    // time between checks
+    pub(crate) interval: chrono::Duration,
+
+    // time single probe has to pass
+    pub(crate) timeout: chrono::Duration,
+
+    // number of consecutive failures after which process is restarted
+    pub(crate) failures: u32,
 }
 
-#[derive(Debug)]
+// delay between restarts, growing while process keeps failing quickly
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RestartPolicy {
+    // delay before first restart
+    pub(crate) delay: chrono::Duration,
+
+    // factor delay grows by after each restart
+    pub(crate) multiplier: f64,
+
+    // longest delay
+    pub(crate) max_delay: chrono::Duration,
+
+    // fraction of delay it is randomly shortened or extended by, between 0 and 1
+    pub(crate) jitter: f64,
+
+    // time process has to stay up for delay to reset
+    pub(crate) stable_period: chrono::Duration,
+}
+
+// limits on crash folders kept in crash path. Oldest folders over any of the limits are deleted
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct CrashRetention {
+    // number of folders
+    pub(crate) max_folders: Option<usize>,
+
+    // age of a folder
+    pub(crate) max_age: Option<chrono::Duration>,
+
+    // total size of folders in bytes
+    pub(crate) max_size: Option<u64>,
+}
+
+// output streams written to command log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LogStreams {
+    Stdout,
+    Stderr,
+    Both,
+}
+
+// continuous log of process output, rotated once it grows too large or too old
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LogConfig {
+    // path of the current log file. Rotated files get suffix .1 (newest) to .<keep> (oldest)
+    pub(crate) path: String,
+
+    // which output streams are logged
+    pub(crate) streams: LogStreams,
+
+    // size in bytes log file is rotated at
+    pub(crate) max_size: Option<u64>,
+
+    // age log file is rotated at
+    pub(crate) rotate_every: Option<chrono::Duration>,
+
+    // number of rotated files kept
+    pub(crate) keep: usize,
+}
+
+// limits on resources used by a process together with its descendants. Process over a limit is
+// killed and handled as a crash
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ResourceLimits {
+    // resident memory in bytes
+    pub(crate) max_memory: Option<u64>,
+
+    // cpu usage in percent of a single core
+    pub(crate) max_cpu: Option<f64>,
+
+    // time cpu usage has to stay over its limit
+    pub(crate) max_cpu_for: chrono::Duration,
+}
+
+impl Default for ResourceLimits {
+    // no limits
+    fn default() -> ResourceLimits {
+        ResourceLimits {
+            max_memory: None,
+            max_cpu: None,
+            max_cpu_for: chrono::Duration::seconds(DEFAULT_MAX_CPU_FOR_S),
+        }
+    }
+}
+
+// resource limit set with setrlimit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Rlimit {
+    // number of open file descriptors, RLIMIT_NOFILE
+    OpenFiles,
+
+    // size of core dumps in bytes, RLIMIT_CORE
+    Core,
+
+    // size of virtual memory in bytes, RLIMIT_AS
+    AddressSpace,
+
+    // cpu time in seconds, RLIMIT_CPU
+    Cpu,
+}
+
+// io scheduling class set with ioprio_set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+// settings applied to the process after it is started and before it executes the command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ProcessSettings {
+    // resource limits, each setting both soft and hard limit
+    pub(crate) rlimits: Vec<(Rlimit, libc::rlim_t)>,
+
+    // scheduling priority, from -20 (highest) to 19 (lowest)
+    pub(crate) nice: Option<i32>,
+
+    // io scheduling class and priority within it, from 0 (highest) to 7 (lowest)
+    pub(crate) ionice: Option<(IoClass, u8)>,
+
+    // file mode creation mask
+    pub(crate) umask: Option<u32>,
+}
+
+// single stage of backup strategy. Its actions run once process crashes more than given times in
+// a period, after which runner escalates to the next stage
+#[derive(Debug, Clone)]
 pub(crate) struct BackupStrategy {
     pub(crate) times: u64,
     pub(crate) period: chrono::Duration,
+
+    // script to run once
     pub(crate) script: Option<String>,
+
+    // arguments to run command with once
     pub(crate) safe_mode: Option<Vec<String>>,
+
+    // command and arguments to run instead of configured ones from now on
+    pub(crate) fallback: Option<(String, Vec<String>)>,
+
+    // whether runner gives up on the command after running the actions
+    pub(crate) give_up: bool,
 }
 
 impl CommandConfig {
@@ -81,14 +373,66 @@ impl CommandConfig {
         Ok(CommandConfig {
             command: command.clone(),
             args: CommandConfig::parse_args(json)?,
-            stdout_history: CommandConfig::parse_history(json)?,
+            stdout_history: CommandConfig::parse_line_count(
+                json,
+                "stdout history",
+                DEFAULT_HISTORY,
+            )?,
+            scrollback: CommandConfig::parse_line_count(json, "scrollback", DEFAULT_SCROLLBACK)?,
             mode: CommandConfig::parse_mode(json)?,
             name: CommandConfig::parse_name(json)
                 .map_or_else(|| CommandConfig::get_name(&command), Ok)?,
             backup_strategy: CommandConfig::parse_backup_strategy(json)?,
+            stop_signal: CommandConfig::parse_stop_signal(json)?,
+            stop_timeout: CommandConfig::parse_duration(json, "stop timeout")?
+                .unwrap_or_else(|| chrono::Duration::seconds(DEFAULT_STOP_TIMEOUT_S)),
+            cwd: CommandConfig::parse_cwd(json)?,
+            env: CommandConfig::parse_env(json)?,
+            clear_env: CommandConfig::parse_clear_env(json)?,
+            depends_on: CommandConfig::parse_depends_on(json)?,
+            ready_when: CommandConfig::parse_ready_when(json)?,
+            health_checks: CommandConfig::parse_health_checks(json)?,
+            restart: CommandConfig::parse_restart_policy(json)?,
+            crash_retention: CommandConfig::parse_crash_retention(json)?,
+            log: CommandConfig::parse_log(json)?,
+            limits: CommandConfig::parse_resource_limits(json)?,
+            process: CommandConfig::parse_process_settings(json)?,
         })
     }
 
+    // config running given command once, in place of this one. Used for backup strategy actions,
+    // which inherit process settings but none of the supervision
+    pub(crate) fn derive(&self, command: String, args: Vec<String>, name: String) -> CommandConfig {
+        CommandConfig {
+            command,
+            args,
+            stdout_history: self.stdout_history,
+            scrollback: self.scrollback,
+            mode: CommandMode::RunOnceAndWait,
+            name,
+            backup_strategy: Vec::new(),
+            stop_signal: self.stop_signal,
+            stop_timeout: self.stop_timeout,
+            cwd: self.cwd.clone(),
+            env: self.env.clone(),
+            clear_env: self.clear_env,
+            depends_on: Vec::new(),
+            ready_when: None,
+            health_checks: Vec::new(),
+            restart: self.restart.clone(),
+            crash_retention: self.crash_retention.clone(),
+            log: None,
+            limits: ResourceLimits::default(),
+            process: self.process.clone(),
+        }
+    }
+
+    // whether commands depending on this one can start as soon as it is started. Long running
+    // services without readiness checks are, others first have to become ready or exit successfully
+    pub(crate) fn satisfied_when_started(&self) -> bool {
+        self.ready_when.is_none() && matches!(self.mode, CommandMode::KeepAlive)
+    }
+
     // parses command part of configuration. This field must be present in configuration
     fn parse_command(json: &serde_json::Value) -> Result<String, ConfigError> {
         Ok(json
@@ -119,17 +463,18 @@ impl CommandConfig {
         })
     }
 
-    // parses history (number of lines for stdout)
-    fn parse_history(json: &serde_json::Value) -> Result<usize, ConfigError> {
-        json.get("stdout history").map_or_else(
-            || Ok(DEFAULT_HISTORY),
+    // parses optional number of lines, like stdout history
+    fn parse_line_count(
+        json: &serde_json::Value,
+        field: &str,
+        default: usize,
+    ) -> Result<usize, ConfigError> {
+        json.get(field).map_or_else(
+            || Ok(default),
             |val| {
                 val.as_u64()
                     .ok_or_else(|| {
-                        ConfigError::BadCommandConfig(
-                            String::from("stdout history"),
-                            json.to_string(),
-                        )
+                        ConfigError::BadCommandConfig(String::from(field), json.to_string())
                     })?
                     .try_into()
                     .map_err(|err: TryFromIntError| err.into())
@@ -181,20 +526,493 @@ impl CommandConfig {
             .to_owned())
     }
 
-    fn parse_backup_strategy(
+    // parses working directory. This field is optional
+    fn parse_cwd(json: &serde_json::Value) -> Result<Option<String>, ConfigError> {
+        json.get("cwd").map_or(Ok(None), |val| {
+            val.as_str()
+                .ok_or_else(|| ConfigError::BadCommandConfig(String::from("cwd"), json.to_string()))
+                .map(|val| Some(val.to_owned()))
+        })
+    }
+
+    // parses environment variables from env file and env map. Variables in env map override ones
+    // from env file. Both fields are optional
+    fn parse_env(json: &serde_json::Value) -> Result<Vec<(String, String)>, ConfigError> {
+        let mut env = match json.get("env file") {
+            Some(path) => {
+                let path = path.as_str().ok_or_else(|| {
+                    ConfigError::BadCommandConfig(String::from("env file"), json.to_string())
+                })?;
+                CommandConfig::parse_env_file(&std::fs::read_to_string(path)?)?
+            }
+            None => Vec::new(),
+        };
+
+        if let Some(map) = json.get("env") {
+            let map = map.as_object().ok_or_else(|| {
+                ConfigError::BadCommandConfig(String::from("env"), json.to_string())
+            })?;
+            for (key, value) in map {
+                let value = value.as_str().ok_or_else(|| {
+                    ConfigError::BadCommandConfig(String::from("env"), json.to_string())
+                })?;
+                env.retain(|(existing, _)| existing != key);
+                env.push((key.to_owned(), value.to_owned()));
+            }
+        }
+        Ok(env)
+    }
+
+    // parses content of env file in dotenv format. Empty lines and lines starting with # are
+    // ignored, export prefix and quotes around values are removed
+    fn parse_env_file(content: &str) -> Result<Vec<(String, String)>, ConfigError> {
+        let mut env: Vec<(String, String)> = Vec::new();
+        for line in content.lines().map(|line| line.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                ConfigError::BadCommandConfig(String::from("env file"), line.to_owned())
+            })?;
+            let key = key.trim();
+            if key.is_empty() {
+                return Err(ConfigError::BadCommandConfig(
+                    String::from("env file"),
+                    line.to_owned(),
+                ));
+            }
+            let value = value.trim();
+            let value = ['"', '\'']
+                .iter()
+                .find_map(|quote| {
+                    value
+                        .strip_prefix(*quote)
+                        .and_then(|value| value.strip_suffix(*quote))
+                })
+                .unwrap_or(value);
+            env.retain(|(existing, _)| existing != key);
+            env.push((key.to_owned(), value.to_owned()));
+        }
+        Ok(env)
+    }
+
+    // parses clear env flag. This field is optional, environment is inherited by default
+    fn parse_clear_env(json: &serde_json::Value) -> Result<bool, ConfigError> {
+        json.get("clear env").map_or(Ok(false), |val| {
+            val.as_bool().ok_or_else(|| {
+                ConfigError::BadCommandConfig(String::from("clear env"), json.to_string())
+            })
+        })
+    }
+
+    // parses names of commands this command depends on. This field is optional
+    fn parse_depends_on(json: &serde_json::Value) -> Result<Vec<String>, ConfigError> {
+        json.get("depends on").map_or(Ok(Vec::new()), |val| {
+            val.as_array()
+                .ok_or_else(|| {
+                    ConfigError::BadCommandConfig(String::from("depends on"), json.to_string())
+                })?
+                .iter()
+                .map(|e| {
+                    e.as_str()
+                        .ok_or_else(|| {
+                            ConfigError::BadCommandConfig(
+                                String::from("depends on"),
+                                json.to_string(),
+                            )
+                        })
+                        .map(|val| val.to_owned())
+                })
+                .collect::<Result<Vec<String>, ConfigError>>()
+        })
+    }
+
+    // parses readiness checks. This field is optional, but when given at least one check is needed
+    fn parse_ready_when(json: &serde_json::Value) -> Result<Option<ReadyWhen>, ConfigError> {
+        let json = match json.get("ready when") {
+            Some(json) => json,
+            None => return Ok(None),
+        };
+        let bad_config =
+            || ConfigError::BadCommandConfig(String::from("ready when"), json.to_string());
+
+        let tcp_port = CommandConfig::parse_tcp_port(json, "ready when")?;
+        let file = json
+            .get("file")
+            .map(|file| {
+                file.as_str()
+                    .map(|file| file.to_owned())
+                    .ok_or_else(bad_config)
+            })
+            .transpose()?;
+        let log_pattern = json
+            .get("log pattern")
+            .map(|pattern| {
+                pattern
+                    .as_str()
+                    .and_then(|pattern| Regex::new(pattern).ok())
+                    .ok_or_else(bad_config)
+            })
+            .transpose()?;
+        let command = CommandConfig::parse_probe_command(json, "ready when")?;
+        if tcp_port.is_none() && file.is_none() && log_pattern.is_none() && command.is_none() {
+            return Err(bad_config());
+        }
+
+        let on_failure = match json.get("on failure").map(|action| action.as_str()) {
+            None | Some(Some("restart")) => ReadyFailure::Restart,
+            Some(Some("stop")) => ReadyFailure::Stop,
+            Some(Some("ignore")) => ReadyFailure::Ignore,
+            Some(_) => return Err(bad_config()),
+        };
+
+        Ok(Some(ReadyWhen {
+            tcp_port,
+            file,
+            log_pattern,
+            command,
+            interval: CommandConfig::parse_duration(json, "interval")?
+                .unwrap_or_else(|| chrono::Duration::seconds(DEFAULT_READY_INTERVAL_S)),
+            timeout: CommandConfig::parse_duration(json, "timeout")?
+                .unwrap_or_else(|| chrono::Duration::seconds(DEFAULT_READY_TIMEOUT_S)),
+            on_failure,
+        }))
+    }
+
+    // parses liveness checks. Each check has exactly one probe
+    fn parse_health_checks(json: &serde_json::Value) -> Result<Vec<HealthCheck>, ConfigError> {
+        let checks = match json.get("health checks") {
+            Some(checks) => checks.as_array().ok_or_else(|| {
+                ConfigError::BadCommandConfig(String::from("health checks"), checks.to_string())
+            })?,
+            None => return Ok(Vec::new()),
+        };
+        checks
+            .iter()
+            .map(|json| {
+                let bad_config = || {
+                    ConfigError::BadCommandConfig(String::from("health checks"), json.to_string())
+                };
+                let command = CommandConfig::parse_probe_command(json, "health checks")?;
+                let tcp_port = CommandConfig::parse_tcp_port(json, "health checks")?;
+                let silence = CommandConfig::parse_duration(json, "stdout silence")?;
+                let probe = match (command, tcp_port, silence) {
+                    (Some(command), None, None) => HealthProbe::Command(command),
+                    (None, Some(port), None) => HealthProbe::TcpPort(port),
+                    (None, None, Some(silence)) => HealthProbe::StdoutSilence(silence),
+                    _ => return Err(bad_config()),
+                };
+                let failures = match json.get("failures") {
+                    Some(failures) => failures
+                        .as_u64()
+                        .filter(|&failures| failures > 0)
+                        .and_then(|failures| u32::try_from(failures).ok())
+                        .ok_or_else(bad_config)?,
+                    None => DEFAULT_HEALTH_FAILURES,
+                };
+                Ok(HealthCheck {
+                    probe,
+                    interval: CommandConfig::parse_duration(json, "interval")?
+                        .unwrap_or_else(|| chrono::Duration::seconds(DEFAULT_HEALTH_INTERVAL_S)),
+                    timeout: CommandConfig::parse_duration(json, "timeout")?
+                        .unwrap_or_else(|| chrono::Duration::seconds(DEFAULT_HEALTH_TIMEOUT_S)),
+                    failures,
+                })
+            })
+            .collect()
+    }
+
+    // parses restart delay options. All of them are optional, without restart delay processes
+    // are restarted right away
+    fn parse_restart_policy(json: &serde_json::Value) -> Result<RestartPolicy, ConfigError> {
+        let parse_number =
+            |field: &str, default: f64, valid: fn(f64) -> bool| match json.get(field) {
+                Some(value) => value.as_f64().filter(|&value| valid(value)).ok_or_else(|| {
+                    ConfigError::BadCommandConfig(field.to_owned(), value.to_string())
+                }),
+                None => Ok(default),
+            };
+        Ok(RestartPolicy {
+            delay: CommandConfig::parse_duration(json, "restart delay")?
+                .unwrap_or_else(chrono::Duration::zero),
+            multiplier: parse_number("backoff multiplier", DEFAULT_BACKOFF_MULTIPLIER, |value| {
+                (1f64..=MAX_BACKOFF_MULTIPLIER).contains(&value)
+            })?,
+            max_delay: CommandConfig::parse_duration(json, "max delay")?
+                .unwrap_or_else(|| chrono::Duration::seconds(DEFAULT_MAX_DELAY_S)),
+            jitter: parse_number("jitter", 0f64, |value| (0f64..=1f64).contains(&value))?,
+            stable_period: CommandConfig::parse_duration(json, "stable period")?
+                .unwrap_or_else(|| chrono::Duration::seconds(DEFAULT_STABLE_PERIOD_S)),
+        })
+    }
+
+    // parses crash folder limits. Used both for commands and entire configuration, all limits are
+    // optional
+    pub(crate) fn parse_crash_retention(
         json: &serde_json::Value,
-    ) -> Result<Option<BackupStrategy>, ConfigError> {
-        let json = match json.get("backup strategy") {
+    ) -> Result<CrashRetention, ConfigError> {
+        let json = match json.get("crash retention") {
+            Some(json) => json,
+            None => return Ok(CrashRetention::default()),
+        };
+        let max_folders = json
+            .get("max folders")
+            .map(|count| {
+                count
+                    .as_u64()
+                    .and_then(|count| usize::try_from(count).ok())
+                    .ok_or_else(|| {
+                        ConfigError::BadCommandConfig(String::from("max folders"), json.to_string())
+                    })
+            })
+            .transpose()?;
+        Ok(CrashRetention {
+            max_folders,
+            max_age: CommandConfig::parse_duration(json, "max age")?,
+            max_size: CommandConfig::parse_size(json, "max size")?,
+        })
+    }
+
+    // parses continuous log of the process. This field is optional, when given it needs a path
+    fn parse_log(json: &serde_json::Value) -> Result<Option<LogConfig>, ConfigError> {
+        let json = match json.get("log") {
             Some(json) => json,
             None => return Ok(None),
         };
+        let bad_config =
+            |field: &str| ConfigError::BadCommandConfig(field.to_owned(), json.to_string());
+        let path = json
+            .get("path")
+            .and_then(|path| path.as_str())
+            .ok_or_else(|| bad_config("path"))?
+            .to_owned();
+        let streams = match json.get("streams").map(|streams| streams.as_str()) {
+            None | Some(Some("both")) => LogStreams::Both,
+            Some(Some("stdout")) => LogStreams::Stdout,
+            Some(Some("stderr")) => LogStreams::Stderr,
+            Some(_) => return Err(bad_config("streams")),
+        };
+        let keep = match json.get("keep") {
+            Some(keep) => keep
+                .as_u64()
+                .and_then(|keep| usize::try_from(keep).ok())
+                .ok_or_else(|| bad_config("keep"))?,
+            None => DEFAULT_LOG_KEEP,
+        };
+        Ok(Some(LogConfig {
+            path,
+            streams,
+            max_size: CommandConfig::parse_size(json, "max size")?,
+            rotate_every: CommandConfig::parse_duration(json, "rotate every")?,
+            keep,
+        }))
+    }
+
+    // parses resource limits. All of them are optional, without them process can use any amount
+    // of resources
+    fn parse_resource_limits(json: &serde_json::Value) -> Result<ResourceLimits, ConfigError> {
+        let max_cpu = json
+            .get("max cpu")
+            .map(|max_cpu| {
+                max_cpu
+                    .as_f64()
+                    .filter(|&max_cpu| max_cpu > 0f64)
+                    .ok_or_else(|| {
+                        ConfigError::BadCommandConfig(String::from("max cpu"), max_cpu.to_string())
+                    })
+            })
+            .transpose()?;
+        Ok(ResourceLimits {
+            max_memory: CommandConfig::parse_size(json, "max memory")?,
+            max_cpu,
+            max_cpu_for: CommandConfig::parse_duration(json, "max cpu for")?
+                .unwrap_or_else(|| chrono::Duration::seconds(DEFAULT_MAX_CPU_FOR_S)),
+        })
+    }
+
+    // parses rlimits, nice, ionice and umask. All of them are optional, without them process
+    // inherits settings of runner
+    fn parse_process_settings(json: &serde_json::Value) -> Result<ProcessSettings, ConfigError> {
+        let bad_config = |field: &str, json: &serde_json::Value| {
+            ConfigError::BadCommandConfig(field.to_owned(), json.to_string())
+        };
+        let mut rlimits = Vec::new();
+        if let Some(limits_json) = json.get("rlimits") {
+            let limits = limits_json
+                .as_object()
+                .ok_or_else(|| bad_config("rlimits", limits_json))?;
+            for (name, value) in limits {
+                let rlimit = match name.as_str() {
+                    "nofile" => Rlimit::OpenFiles,
+                    "core" => Rlimit::Core,
+                    "as" => Rlimit::AddressSpace,
+                    "cpu" => Rlimit::Cpu,
+                    _ => return Err(bad_config("rlimits", limits_json)),
+                };
+                let limit = if value.as_str() == Some("unlimited") {
+                    libc::RLIM_INFINITY
+                } else {
+                    match rlimit {
+                        Rlimit::OpenFiles => value.as_u64(),
+                        Rlimit::Core | Rlimit::AddressSpace => {
+                            CommandConfig::parse_size(limits_json, name)?
+                        }
+                        Rlimit::Cpu => CommandConfig::parse_duration(limits_json, name)?
+                            .and_then(|cpu| u64::try_from(cpu.num_seconds()).ok()),
+                    }
+                    .ok_or_else(|| bad_config(name, value))?
+                };
+                rlimits.push((rlimit, limit));
+            }
+        }
+        let nice = json
+            .get("nice")
+            .map(|nice| {
+                nice.as_i64()
+                    .filter(|nice| (-20..=19).contains(nice))
+                    .map(|nice| nice as i32)
+                    .ok_or_else(|| bad_config("nice", nice))
+            })
+            .transpose()?;
+        let ionice = json
+            .get("ionice")
+            .map(|ionice| {
+                let class = match ionice.get("class").and_then(|class| class.as_str()) {
+                    Some("realtime") => IoClass::Realtime,
+                    Some("best effort") => IoClass::BestEffort,
+                    Some("idle") => IoClass::Idle,
+                    _ => return Err(bad_config("ionice", ionice)),
+                };
+                let level = match ionice.get("level") {
+                    Some(level) => level
+                        .as_u64()
+                        .filter(|&level| level <= 7)
+                        .ok_or_else(|| bad_config("ionice", ionice))?,
+                    None => DEFAULT_IONICE_LEVEL,
+                };
+                Ok((class, level as u8))
+            })
+            .transpose()?;
+        let umask = json
+            .get("umask")
+            .map(|umask| {
+                umask
+                    .as_str()
+                    .and_then(|umask| u32::from_str_radix(umask, 8).ok())
+                    .filter(|&umask| umask <= 0o777)
+                    .ok_or_else(|| bad_config("umask", umask))
+            })
+            .transpose()?;
+        Ok(ProcessSettings {
+            rlimits,
+            nice,
+            ionice,
+            umask,
+        })
+    }
 
-        Ok(Some(BackupStrategy {
+    // parses size in bytes, either a number or "<number><unit>" with units B, KB, MB and GB.
+    // Returns None if field is missing
+    fn parse_size(json: &serde_json::Value, field: &str) -> Result<Option<u64>, ConfigError> {
+        let bad_config = || ConfigError::BadCommandConfig(String::from(field), json.to_string());
+        let size = match json.get(field) {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+        if let Some(bytes) = size.as_u64() {
+            return Ok(Some(bytes));
+        }
+        let size = size.as_str().ok_or_else(bad_config)?;
+        let split = size
+            .find(|c: char| c.is_alphabetic())
+            .ok_or_else(bad_config)?;
+        let number = size[..split].parse::<u64>().map_err(|_| bad_config())?;
+        let unit = match &size[split..] {
+            "B" => 1u64,
+            "KB" => 1u64 << 10,
+            "MB" => 1u64 << 20,
+            "GB" => 1u64 << 30,
+            _ => return Err(bad_config()),
+        };
+        number.checked_mul(unit).map(Some).ok_or_else(bad_config)
+    }
+
+    // parses optional local tcp port of a check
+    fn parse_tcp_port(json: &serde_json::Value, field: &str) -> Result<Option<u16>, ConfigError> {
+        json.get("tcp port")
+            .map(|port| {
+                port.as_u64()
+                    .and_then(|port| u16::try_from(port).ok())
+                    .ok_or_else(|| {
+                        ConfigError::BadCommandConfig(field.to_owned(), json.to_string())
+                    })
+            })
+            .transpose()
+    }
+
+    // parses optional probe command of a check, an array of command and its arguments
+    fn parse_probe_command(
+        json: &serde_json::Value,
+        field: &str,
+    ) -> Result<Option<Vec<String>>, ConfigError> {
+        let bad_config = || ConfigError::BadCommandConfig(field.to_owned(), json.to_string());
+        json.get("command")
+            .map(|command| {
+                command
+                    .as_array()
+                    .filter(|command| !command.is_empty())
+                    .ok_or_else(bad_config)?
+                    .iter()
+                    .map(|arg| {
+                        arg.as_str()
+                            .map(|arg| arg.to_owned())
+                            .ok_or_else(bad_config)
+                    })
+                    .collect::<Result<Vec<String>, ConfigError>>()
+            })
+            .transpose()
+    }
+
+    // parses backup strategy, either a single stage or an array of stages. This field is optional
+    fn parse_backup_strategy(json: &serde_json::Value) -> Result<Vec<BackupStrategy>, ConfigError> {
+        match json.get("backup strategy") {
+            Some(serde_json::Value::Array(stages)) => stages
+                .iter()
+                .map(CommandConfig::parse_backup_stage)
+                .collect(),
+            Some(json) => Ok(vec![CommandConfig::parse_backup_stage(json)?]),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // parses single stage of backup strategy. Stage without any action gives up on the command
+    fn parse_backup_stage(json: &serde_json::Value) -> Result<BackupStrategy, ConfigError> {
+        let script = CommandConfig::parse_backup_strategy_script(json)?;
+        let safe_mode = CommandConfig::parse_backup_strategy_args(json)?;
+        let fallback = json
+            .get("fallback")
+            .map(|fallback| {
+                Ok::<_, ConfigError>((
+                    CommandConfig::parse_command(fallback)?,
+                    CommandConfig::parse_args(fallback)?,
+                ))
+            })
+            .transpose()?;
+        let give_up = match json.get("give up") {
+            Some(give_up) => give_up.as_bool().ok_or_else(|| {
+                ConfigError::BadCommandConfig(String::from("give up"), json.to_string())
+            })?,
+            None => script.is_none() && safe_mode.is_none() && fallback.is_none(),
+        };
+        Ok(BackupStrategy {
             times: CommandConfig::parse_backup_strategy_times(json)?,
             period: CommandConfig::parse_backup_strategy_period(json)?,
-            script: CommandConfig::parse_backup_strategy_script(json)?,
-            safe_mode: CommandConfig::parse_backup_strategy_args(json)?,
-        }))
+            script,
+            safe_mode,
+            fallback,
+            give_up,
+        })
     }
 
     fn parse_backup_strategy_times(json: &serde_json::Value) -> Result<u64, ConfigError> {
@@ -217,53 +1035,61 @@ impl CommandConfig {
     fn parse_backup_strategy_period(
         json: &serde_json::Value,
     ) -> Result<chrono::Duration, ConfigError> {
-        let period = json
-            .get("period")
-            .ok_or_else(|| {
-                ConfigError::BadCommandConfig(
-                    String::from("backup strategy period"),
+        CommandConfig::parse_duration(json, "period")?.ok_or_else(|| {
+            ConfigError::BadCommandConfig(String::from("backup strategy period"), json.to_string())
+        })
+    }
+
+    // parses signal name given in stop signal field. This field is optional
+    fn parse_stop_signal(json: &serde_json::Value) -> Result<i32, ConfigError> {
+        json.get("stop signal")
+            .map_or(Ok(DEFAULT_STOP_SIGNAL), |val| match val.as_str() {
+                Some("SIGTERM") => Ok(libc::SIGTERM),
+                Some("SIGINT") => Ok(libc::SIGINT),
+                Some("SIGQUIT") => Ok(libc::SIGQUIT),
+                Some("SIGHUP") => Ok(libc::SIGHUP),
+                Some("SIGUSR1") => Ok(libc::SIGUSR1),
+                Some("SIGUSR2") => Ok(libc::SIGUSR2),
+                Some("SIGKILL") => Ok(libc::SIGKILL),
+                _ => Err(ConfigError::BadCommandConfig(
+                    String::from("stop signal"),
                     json.to_string(),
-                )
-            })?
+                )),
+            })
+    }
+
+    // parses duration in "<number><unit>" format from a given field. Returns None if field is missing
+    fn parse_duration(
+        json: &serde_json::Value,
+        field: &str,
+    ) -> Result<Option<chrono::Duration>, ConfigError> {
+        let duration = match json.get(field) {
+            Some(duration) => duration,
+            None => return Ok(None),
+        };
+        let duration = duration
             .as_str()
-            .ok_or_else(|| {
-                ConfigError::BadCommandConfig(
-                    String::from("backup strategy period"),
-                    json.to_string(),
-                )
-            })?;
-        let number: i64 = period
-            .get(..period.len() - 1)
-            .ok_or_else(|| {
-                ConfigError::BadCommandConfig(
-                    String::from("backup strategy period"),
-                    json.to_string(),
-                )
-            })?
+            .ok_or_else(|| ConfigError::BadCommandConfig(String::from(field), json.to_string()))?;
+        let (number, unit) =
+            duration.split_at(duration.find(|c: char| c.is_alphabetic()).unwrap_or(0));
+        let number: i64 = number
             .parse()
-            .map_err(|_| {
-                ConfigError::BadCommandConfig(
-                    String::from("backup strategy period"),
-                    json.to_string(),
-                )
-            })?;
+            .ok()
+            .filter(|&number| number > 0)
+            .ok_or_else(|| ConfigError::BadCommandConfig(String::from(field), json.to_string()))?;
 
-        if period.ends_with('s') {
-            return Ok(chrono::Duration::seconds(number));
-        } else if period.ends_with('m') {
-            return Ok(chrono::Duration::minutes(number));
-        } else if period.ends_with('h') {
-            return Ok(chrono::Duration::hours(number));
-        } else if period.ends_with('d') {
-            return Ok(chrono::Duration::days(number));
-        } else if period.ends_with('w') {
-            return Ok(chrono::Duration::weeks(number));
+        match unit {
+            "ms" => Ok(Some(chrono::Duration::milliseconds(number))),
+            "s" => Ok(Some(chrono::Duration::seconds(number))),
+            "m" => Ok(Some(chrono::Duration::minutes(number))),
+            "h" => Ok(Some(chrono::Duration::hours(number))),
+            "d" => Ok(Some(chrono::Duration::days(number))),
+            "w" => Ok(Some(chrono::Duration::weeks(number))),
+            _ => Err(ConfigError::BadCommandConfig(
+                String::from(field),
+                json.to_string(),
+            )),
         }
-
-        Err(ConfigError::BadCommandConfig(
-            String::from("backup strategy duration"),
-            json.to_string(),
-        ))
     }
 
     fn parse_backup_strategy_script(
@@ -380,12 +1206,13 @@ mod tests {
         });
         let config = CommandConfig::parse_backup_strategy(&json)
             .unwrap()
-            .unwrap();
+            .remove(0);
 
         assert_eq!(config.times, 5u64);
         assert_eq!(config.period, chrono::Duration::minutes(1));
         assert_eq!(config.safe_mode, None);
         assert_eq!(config.script, None);
+        assert!(config.give_up);
 
         let json = json!({
             "backup strategy": {
@@ -397,7 +1224,7 @@ mod tests {
         });
         let config = CommandConfig::parse_backup_strategy(&json)
             .unwrap()
-            .unwrap();
+            .remove(0);
 
         assert_eq!(config.times, 13u64);
         assert_eq!(config.period, chrono::Duration::weeks(125));
@@ -406,5 +1233,437 @@ mod tests {
             Some(vec![String::from("safe"), String::from("mode")])
         );
         assert_eq!(config.script, Some(String::from("cleanup.sh")));
+        assert!(!config.give_up);
+
+        let json = json!({ "backup strategy": { "times": 1u64, "period": "1500ms" } });
+        let config = CommandConfig::parse_backup_strategy(&json)
+            .unwrap()
+            .remove(0);
+        assert_eq!(config.period, chrono::Duration::milliseconds(1500));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let json = json!({ "period": "90s" });
+        assert_eq!(
+            CommandConfig::parse_duration(&json, "period").unwrap(),
+            Some(chrono::Duration::seconds(90))
+        );
+        assert_eq!(CommandConfig::parse_duration(&json, "other").unwrap(), None);
+
+        for period in ["-5s", "0s", "5", "s", "5y", "1.5h"] {
+            let json = json!({ "period": period });
+            assert!(matches!(
+                CommandConfig::parse_duration(&json, "period"),
+                Err(ConfigError::BadCommandConfig(_, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_backup_stages() {
+        let json = json!({
+            "backup strategy": [
+                { "times": 3, "period": "1m" , "give up": false },
+                { "times": 3, "period": "5m", "script": "cleanup.sh" },
+                { "times": 2, "period": "5m", "safe mode": ["--safe"] },
+                { "times": 2, "period": "10m", "fallback": { "command": "./old-server", "args": ["-v"] } },
+                { "times": 1, "period": "1h", "script": "alert.sh", "give up": true }
+            ]
+        });
+        let stages = CommandConfig::parse_backup_strategy(&json).unwrap();
+        assert_eq!(stages.len(), 5);
+        assert!(!stages[0].give_up);
+        assert_eq!(stages[1].script, Some(String::from("cleanup.sh")));
+        assert_eq!(stages[2].safe_mode, Some(vec![String::from("--safe")]));
+        assert_eq!(
+            stages[3].fallback,
+            Some((String::from("./old-server"), vec![String::from("-v")]))
+        );
+        assert!(!stages[3].give_up);
+        assert!(stages[4].give_up);
+
+        let json = json!({ "backup strategy": [{ "times": 3, "period": "1m", "fallback": {} }] });
+        CommandConfig::parse_backup_strategy(&json).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_stop() {
+        let json = json!({
+            "command": "./server"
+        });
+        let config = CommandConfig::parse_config(&json).unwrap();
+        assert_eq!(config.stop_signal, libc::SIGTERM);
+        assert_eq!(config.stop_timeout, chrono::Duration::seconds(10));
+
+        let json = json!({
+            "command": "./server",
+            "stop signal": "SIGINT",
+            "stop timeout": "2m"
+        });
+        let config = CommandConfig::parse_config(&json).unwrap();
+        assert_eq!(config.stop_signal, libc::SIGINT);
+        assert_eq!(config.stop_timeout, chrono::Duration::minutes(2));
+
+        let json = json!({
+            "command": "./server",
+            "stop signal": "SIGFOO"
+        });
+        CommandConfig::parse_config(&json).unwrap_err();
+
+        let json = json!({
+            "command": "./server",
+            "stop timeout": "2x"
+        });
+        CommandConfig::parse_config(&json).unwrap_err();
+
+        let json = json!({
+            "command": "./server",
+            "stop timeout": "s"
+        });
+        CommandConfig::parse_config(&json).unwrap_err();
+
+        let json = json!({
+            "command": "./server",
+            "stop timeout": "500ms"
+        });
+        let config = CommandConfig::parse_config(&json).unwrap();
+        assert_eq!(config.stop_timeout, chrono::Duration::milliseconds(500));
+    }
+    #[test]
+    fn test_parse_env() {
+        let json = json!({
+            "command": "./server",
+            "cwd": "/srv",
+            "env": { "PORT": "8080", "MODE": "debug" },
+            "clear env": true
+        });
+        let config = CommandConfig::parse_config(&json).unwrap();
+        assert_eq!(config.cwd, Some(String::from("/srv")));
+        assert!(config
+            .env
+            .contains(&(String::from("PORT"), String::from("8080"))));
+        assert!(config
+            .env
+            .contains(&(String::from("MODE"), String::from("debug"))));
+        assert!(config.clear_env);
+
+        let json = json!({
+            "command": "./server",
+            "env": { "PORT": 8080 }
+        });
+        CommandConfig::parse_config(&json).unwrap_err();
+
+        let json = json!({
+            "command": "./server",
+            "env file": "/this/file/does/not/exist.env"
+        });
+        CommandConfig::parse_config(&json).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        let env = CommandConfig::parse_env_file(
+            "# comment\n\nPORT=8080\nexport NAME = \"runner\"\nQUOTED='a b'\nPORT=9090\nEMPTY=\n",
+        )
+        .unwrap();
+        assert_eq!(
+            env,
+            vec![
+                (String::from("NAME"), String::from("runner")),
+                (String::from("QUOTED"), String::from("a b")),
+                (String::from("PORT"), String::from("9090")),
+                (String::from("EMPTY"), String::new()),
+            ]
+        );
+
+        CommandConfig::parse_env_file("NOT A VARIABLE").unwrap_err();
+        CommandConfig::parse_env_file("=value").unwrap_err();
+    }
+    #[test]
+    fn test_parse_ready_when() {
+        let json = json!({
+            "command": "./server",
+            "mode": "keep alive",
+            "ready when": {
+                "tcp port": 8080,
+                "file": "/tmp/server.ready",
+                "log pattern": "listening on [0-9]+",
+                "command": ["curl", "-f", "localhost:8080"],
+                "interval": "500ms",
+                "timeout": "30s",
+                "on failure": "stop"
+            }
+        });
+        let config = CommandConfig::parse_config(&json).unwrap();
+        assert!(!config.satisfied_when_started());
+        let ready_when = config.ready_when.unwrap();
+        assert_eq!(ready_when.tcp_port, Some(8080));
+        assert_eq!(ready_when.file, Some(String::from("/tmp/server.ready")));
+        assert!(ready_when
+            .log_pattern
+            .unwrap()
+            .is_match("listening on 8080"));
+        assert_eq!(
+            ready_when.command,
+            Some(vec![
+                String::from("curl"),
+                String::from("-f"),
+                String::from("localhost:8080")
+            ])
+        );
+        assert_eq!(ready_when.interval, chrono::Duration::milliseconds(500));
+        assert_eq!(ready_when.timeout, chrono::Duration::seconds(30));
+        assert_eq!(ready_when.on_failure, ReadyFailure::Stop);
+
+        let json = json!({
+            "command": "./server",
+            "ready when": { "tcp port": 8080 }
+        });
+        let ready_when = CommandConfig::parse_config(&json)
+            .unwrap()
+            .ready_when
+            .unwrap();
+        assert_eq!(ready_when.timeout, chrono::Duration::seconds(60));
+        assert_eq!(ready_when.on_failure, ReadyFailure::Restart);
+
+        for ready_when in [
+            json!({}),
+            json!({ "tcp port": 70000 }),
+            json!({ "log pattern": "(" }),
+            json!({ "command": [] }),
+            json!({ "file": "ready", "on failure": "explode" }),
+        ] {
+            let json = json!({ "command": "./server", "ready when": ready_when });
+            CommandConfig::parse_config(&json).unwrap_err();
+        }
+    }
+    #[test]
+    fn test_parse_health_checks() {
+        let json = json!({
+            "command": "./server",
+            "health checks": [
+                { "command": ["./check"], "interval": "30s", "timeout": "2s", "failures": 5 },
+                { "tcp port": 8080 },
+                { "stdout silence": "1m" }
+            ]
+        });
+        let checks = CommandConfig::parse_config(&json).unwrap().health_checks;
+        assert_eq!(checks.len(), 3);
+        assert_eq!(
+            checks[0].probe,
+            HealthProbe::Command(vec![String::from("./check")])
+        );
+        assert_eq!(checks[0].interval, chrono::Duration::seconds(30));
+        assert_eq!(checks[0].timeout, chrono::Duration::seconds(2));
+        assert_eq!(checks[0].failures, 5);
+        assert_eq!(checks[1].probe, HealthProbe::TcpPort(8080));
+        assert_eq!(checks[1].interval, chrono::Duration::seconds(10));
+        assert_eq!(checks[1].timeout, chrono::Duration::seconds(5));
+        assert_eq!(checks[1].failures, 3);
+        assert_eq!(
+            checks[2].probe,
+            HealthProbe::StdoutSilence(chrono::Duration::minutes(1))
+        );
+
+        for check in [
+            json!({}),
+            json!({ "tcp port": 8080, "stdout silence": "1m" }),
+            json!({ "tcp port": 8080, "failures": 0 }),
+            json!({ "stdout silence": "soon" }),
+        ] {
+            let json = json!({ "command": "./server", "health checks": [check] });
+            CommandConfig::parse_config(&json).unwrap_err();
+        }
+    }
+    #[test]
+    fn test_parse_restart_policy() {
+        let json = json!({
+            "command": "./server",
+            "restart delay": "500ms",
+            "backoff multiplier": 1.5,
+            "max delay": "1m",
+            "jitter": 0.1,
+            "stable period": "10m"
+        });
+        assert_eq!(
+            CommandConfig::parse_config(&json).unwrap().restart,
+            RestartPolicy {
+                delay: chrono::Duration::milliseconds(500),
+                multiplier: 1.5,
+                max_delay: chrono::Duration::minutes(1),
+                jitter: 0.1,
+                stable_period: chrono::Duration::minutes(10),
+            }
+        );
+
+        let json = json!({ "command": "./server" });
+        let restart = CommandConfig::parse_config(&json).unwrap().restart;
+        assert_eq!(restart.delay, chrono::Duration::zero());
+        assert_eq!(restart.multiplier, 2f64);
+
+        for options in [
+            json!({ "command": "./server", "backoff multiplier": 0.5 }),
+            json!({ "command": "./server", "backoff multiplier": 1e20 }),
+            json!({ "command": "./server", "jitter": 2 }),
+            json!({ "command": "./server", "restart delay": 5 }),
+        ] {
+            CommandConfig::parse_config(&options).unwrap_err();
+        }
+    }
+    #[test]
+    fn test_parse_log() {
+        let json = json!({
+            "log": {
+                "path": "logs/server.log",
+                "streams": "stderr",
+                "max size": "10MB",
+                "rotate every": "1d",
+                "keep": 3
+            }
+        });
+        assert_eq!(
+            CommandConfig::parse_log(&json).unwrap(),
+            Some(LogConfig {
+                path: String::from("logs/server.log"),
+                streams: LogStreams::Stderr,
+                max_size: Some(10 << 20),
+                rotate_every: Some(chrono::Duration::days(1)),
+                keep: 3,
+            })
+        );
+
+        let log = CommandConfig::parse_log(&json!({ "log": { "path": "server.log" } }))
+            .unwrap()
+            .unwrap();
+        assert_eq!(log.streams, LogStreams::Both);
+        assert_eq!(log.max_size, None);
+        assert_eq!(log.rotate_every, None);
+        assert_eq!(log.keep, DEFAULT_LOG_KEEP);
+
+        assert_eq!(CommandConfig::parse_log(&json!({})).unwrap(), None);
+        for log in [
+            json!({}),
+            json!({ "path": "server.log", "streams": "stdin" }),
+            json!({ "path": "server.log", "keep": -1 }),
+        ] {
+            assert!(CommandConfig::parse_log(&json!({ "log": log })).is_err());
+        }
+    }
+
+    #[test]
+    fn test_parse_resource_limits() {
+        let config = CommandConfig::parse_config(&json!({
+            "command": "./server",
+            "max memory": "512MB",
+            "max cpu": 150,
+            "max cpu for": "30s"
+        }))
+        .unwrap();
+        assert_eq!(
+            config.limits,
+            ResourceLimits {
+                max_memory: Some(512 << 20),
+                max_cpu: Some(150f64),
+                max_cpu_for: chrono::Duration::seconds(30),
+            }
+        );
+
+        let config = CommandConfig::parse_config(&json!({ "command": "./server" })).unwrap();
+        assert_eq!(config.limits, ResourceLimits::default());
+
+        for options in [
+            json!({ "command": "./server", "max memory": "512XB" }),
+            json!({ "command": "./server", "max cpu": 0 }),
+            json!({ "command": "./server", "max cpu": "90%" }),
+            json!({ "command": "./server", "max cpu for": 30 }),
+        ] {
+            CommandConfig::parse_config(&options).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_parse_process_settings() {
+        let config = CommandConfig::parse_config(&json!({
+            "command": "./server",
+            "rlimits": { "nofile": 4096, "core": "unlimited", "as": "2GB", "cpu": "1h" },
+            "nice": -5,
+            "ionice": { "class": "idle" },
+            "umask": "027"
+        }))
+        .unwrap();
+        let mut rlimits = config.process.rlimits.clone();
+        rlimits.sort_by_key(|(rlimit, _)| format!("{:?}", rlimit));
+        assert_eq!(
+            rlimits,
+            vec![
+                (Rlimit::AddressSpace, 2 << 30),
+                (Rlimit::Core, libc::RLIM_INFINITY),
+                (Rlimit::Cpu, 3600),
+                (Rlimit::OpenFiles, 4096),
+            ]
+        );
+        assert_eq!(config.process.nice, Some(-5));
+        assert_eq!(
+            config.process.ionice,
+            Some((IoClass::Idle, DEFAULT_IONICE_LEVEL as u8))
+        );
+        assert_eq!(config.process.umask, Some(0o027));
+
+        // backup strategy actions run with the same settings
+        let derived = config.derive(
+            String::from("./cleanup"),
+            Vec::new(),
+            String::from("cleanup"),
+        );
+        assert_eq!(derived.process, config.process);
+
+        let config = CommandConfig::parse_config(&json!({ "command": "./server" })).unwrap();
+        assert_eq!(config.process, ProcessSettings::default());
+
+        for options in [
+            json!({ "command": "./server", "rlimits": { "stack": 1024 } }),
+            json!({ "command": "./server", "rlimits": { "nofile": "many" } }),
+            json!({ "command": "./server", "rlimits": { "cpu": 60 } }),
+            json!({ "command": "./server", "nice": 20 }),
+            json!({ "command": "./server", "ionice": { "class": "fast" } }),
+            json!({ "command": "./server", "ionice": { "class": "realtime", "level": 8 } }),
+            json!({ "command": "./server", "umask": "999" }),
+            json!({ "command": "./server", "umask": 18 }),
+        ] {
+            CommandConfig::parse_config(&options).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn test_parse_crash_retention() {
+        let json = json!({
+            "command": "./server",
+            "crash retention": { "max folders": 20, "max age": "7d", "max size": "100MB" }
+        });
+        assert_eq!(
+            CommandConfig::parse_config(&json).unwrap().crash_retention,
+            CrashRetention {
+                max_folders: Some(20),
+                max_age: Some(chrono::Duration::days(7)),
+                max_size: Some(100 * 1024 * 1024),
+            }
+        );
+
+        let json = json!({ "crash retention": { "max size": 4096 } });
+        let retention = CommandConfig::parse_crash_retention(&json).unwrap();
+        assert_eq!(retention.max_size, Some(4096));
+        assert_eq!(retention.max_folders, None);
+        assert_eq!(retention.max_age, None);
+
+        for retention in [
+            json!({ "max folders": -1 }),
+            json!({ "max size": "10TB" }),
+            json!({ "max size": "MB" }),
+            json!({ "max age": 7 }),
+        ] {
+            CommandConfig::parse_crash_retention(&json!({ "crash retention": retention }))
+                .unwrap_err();
+        }
     }
 }
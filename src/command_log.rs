@@ -0,0 +1,197 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::command_config::{LogConfig, LogStreams};
+
+// continuous log of a single command, shared by all of its runs. File is opened on first line
+pub(crate) struct CommandLog {
+    config: LogConfig,
+    file: Option<BufWriter<File>>,
+
+    // size of current log file
+    size: u64,
+
+    // when current log file was started
+    opened: DateTime<Utc>,
+}
+
+impl CommandLog {
+    // creates log, nothing is written until first line
+    pub(crate) fn new(config: LogConfig) -> CommandLog {
+        CommandLog {
+            config,
+            file: None,
+            size: 0,
+            opened: Utc::now(),
+        }
+    }
+
+    // writes line of stdout or stderr, rotating log first if it is over its limits. Lines of
+    // streams that are not logged are skipped
+    pub(crate) fn write(&mut self, stderr: bool, line: &str, now: DateTime<Utc>) -> io::Result<()> {
+        let (tag, logged) = if stderr {
+            ("err", self.config.streams != LogStreams::Stdout)
+        } else {
+            ("out", self.config.streams != LogStreams::Stderr)
+        };
+        if !logged {
+            return Ok(());
+        }
+        let line = format!("{} {} | {}\n", now.format("%Y-%m-%d %H:%M:%S"), tag, line);
+        if self.file.is_none() {
+            self.open(now)?;
+        }
+        if self.should_rotate(line.len() as u64, now) {
+            self.rotate(now)?;
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(line.as_bytes())?;
+            self.size += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    // writes buffered lines to the file
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    // whether current file has to be rotated before line of given length is written to it. Empty
+    // file is never rotated, so a single line larger than max size is still written
+    fn should_rotate(&self, length: u64, now: DateTime<Utc>) -> bool {
+        self.size > 0
+            && (self
+                .config
+                .max_size
+                .is_some_and(|max_size| self.size + length > max_size)
+                || self
+                    .config
+                    .rotate_every
+                    .is_some_and(|rotate_every| now - self.opened >= rotate_every))
+    }
+
+    // opens log file for appending. Log left behind by previous run of runner is continued, its
+    // age counted from its creation
+    fn open(&mut self, now: DateTime<Utc>) -> io::Result<()> {
+        let path = Path::new(&self.config.path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        self.size = metadata.len();
+        self.opened = match metadata.created().or_else(|_| metadata.modified()) {
+            Ok(created) if self.size > 0 => DateTime::<Utc>::from(created).min(now),
+            _ => now,
+        };
+        self.file = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    // shifts rotated files by one, dropping the oldest, and starts a new file
+    fn rotate(&mut self, now: DateTime<Utc>) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        let path = &self.config.path;
+        let generation = |index: usize| format!("{}.{}", path, index);
+        if self.config.keep == 0 {
+            std::fs::remove_file(path)?;
+        } else {
+            ignore_missing(std::fs::remove_file(generation(self.config.keep)))?;
+            for index in (1..self.config.keep).rev() {
+                ignore_missing(std::fs::rename(generation(index), generation(index + 1)))?;
+            }
+            std::fs::rename(path, generation(1))?;
+        }
+        self.open(now)
+    }
+}
+
+// treats missing file as success, as not all generations exist until log was rotated enough times
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn read(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| line.split(" | ").nth(1).unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_rotation() {
+        let root = TestDir::new("log");
+        let path = root.join("server.log");
+        let mut log = CommandLog::new(LogConfig {
+            path: path.to_string_lossy().into_owned(),
+            streams: LogStreams::Stdout,
+            max_size: Some(64),
+            rotate_every: Some(chrono::Duration::hours(1)),
+            keep: 2,
+        });
+        let start = Utc::now();
+
+        // each line is 35 bytes, so only one fits into a file
+        log.write(false, "first", start).unwrap();
+        log.write(true, "skipped", start).unwrap();
+        log.write(false, "second", start).unwrap();
+        log.write(false, "third", start).unwrap();
+        log.write(false, "fourth", start).unwrap();
+        log.flush().unwrap();
+        assert_eq!(read(&path), vec!["fourth"]);
+        assert_eq!(read(&root.join("server.log.1")), vec!["third"]);
+        assert_eq!(read(&root.join("server.log.2")), vec!["second"]);
+        assert!(!root.join("server.log.3").exists());
+
+        // rotated by age even when small
+        let mut log = CommandLog::new(LogConfig {
+            max_size: None,
+            keep: 0,
+            ..log.config
+        });
+        log.write(false, "old", start).unwrap();
+        log.write(false, "older", start + chrono::Duration::minutes(59))
+            .unwrap();
+        log.write(false, "new", start + chrono::Duration::hours(2))
+            .unwrap();
+        log.flush().unwrap();
+        assert_eq!(read(&path), vec!["new"]);
+    }
+}
@@ -17,13 +17,38 @@ along with this program. If not, see <http://www.gnu.org/licenses/>.
 
 use clap::crate_version;
 
-use crate::{command_config::CommandConfig, config_error::ConfigError};
+use crate::{
+    command_config::{CommandConfig, CrashRetention},
+    config_error::ConfigError,
+};
+
+// format crash folders are packed into
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum CrashArchive {
+    TarGz,
+    Zip,
+}
+
+impl CrashArchive {
+    // file extension of the archive
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            CrashArchive::TarGz => "tar.gz",
+            CrashArchive::Zip => "zip",
+        }
+    }
+}
 
 // All config data parsed out
 #[derive(Debug)]
 pub(crate) struct Config {
     pub(crate) commands: Vec<CommandConfig>,
     pub(crate) crash_path: String,
+    pub(crate) crash_retention: CrashRetention,
+    pub(crate) crash_archive: Option<CrashArchive>,
+    pub(crate) control_socket: Option<String>,
+    pub(crate) dependencies: Vec<Vec<usize>>,
+    pub(crate) start_order: Vec<usize>,
 }
 
 impl Config {
@@ -52,12 +77,83 @@ impl Config {
 
     // parses config from json value
     fn parse_config(json: &serde_json::Value) -> Result<Config, ConfigError> {
+        let commands = Config::parse_commands(json)?;
+        let dependencies = Config::resolve_dependencies(&commands)?;
+        let start_order = Config::sort_dependencies(&commands, &dependencies)?;
         Ok(Config {
-            commands: Config::parse_commands(json)?,
+            commands,
             crash_path: Config::parse_crash_path(json)?,
+            crash_retention: CommandConfig::parse_crash_retention(json)?,
+            crash_archive: Config::parse_crash_archive(json)?,
+            control_socket: Config::parse_control_socket(json)?,
+            dependencies,
+            start_order,
         })
     }
 
+    // resolves dependency names of every command to command ids
+    fn resolve_dependencies(commands: &[CommandConfig]) -> Result<Vec<Vec<usize>>, ConfigError> {
+        commands
+            .iter()
+            .map(|command| {
+                command
+                    .depends_on
+                    .iter()
+                    .map(|name| {
+                        let mut ids = commands
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, other)| &other.name == name)
+                            .map(|(id, _)| id);
+                        match (ids.next(), ids.next()) {
+                            (Some(id), None) => Ok(id),
+                            (Some(_), Some(_)) => {
+                                Err(ConfigError::AmbiguousDependency(name.clone()))
+                            }
+                            (None, _) => Err(ConfigError::UnknownDependency(
+                                command.name.clone(),
+                                name.clone(),
+                            )),
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // orders commands so that every command comes after its dependencies, keeping order of the
+    // configuration file where possible. Reports commands involved in a cycle if there is one
+    fn sort_dependencies(
+        commands: &[CommandConfig],
+        dependencies: &[Vec<usize>],
+    ) -> Result<Vec<usize>, ConfigError> {
+        let mut order = Vec::with_capacity(dependencies.len());
+        let mut started = vec![false; dependencies.len()];
+        while order.len() < dependencies.len() {
+            let next = (0..dependencies.len()).find(|&id| {
+                !started[id]
+                    && dependencies[id]
+                        .iter()
+                        .all(|&dependency| started[dependency])
+            });
+            match next {
+                Some(id) => {
+                    started[id] = true;
+                    order.push(id);
+                }
+                None => {
+                    return Err(ConfigError::DependencyCycle(
+                        (0..dependencies.len())
+                            .filter(|&id| !started[id])
+                            .map(|id| commands[id].name.clone())
+                            .collect(),
+                    ))
+                }
+            }
+        }
+        Ok(order)
+    }
+
     // parses commands part of configuration file. Passes to CommandConfig
     fn parse_commands(json: &serde_json::Value) -> Result<Vec<CommandConfig>, ConfigError> {
         json.get("commands")
@@ -66,7 +162,6 @@ impl Config {
             .ok_or(ConfigError::WrongCommandsFormat)?
             .iter()
             .map(CommandConfig::parse_config)
-            .into_iter()
             .collect::<Result<Vec<CommandConfig>, ConfigError>>()
     }
 
@@ -82,4 +177,97 @@ impl Config {
             })
             .map(|path| path.to_owned())
     }
+
+    // parses optional format of crash archives
+    fn parse_crash_archive(json: &serde_json::Value) -> Result<Option<CrashArchive>, ConfigError> {
+        match json.get("crash archive").map(|format| format.as_str()) {
+            None => Ok(None),
+            Some(Some("tar.gz")) => Ok(Some(CrashArchive::TarGz)),
+            Some(Some("zip")) => Ok(Some(CrashArchive::Zip)),
+            Some(_) => Err(ConfigError::BadCommandConfig(
+                String::from("crash archive"),
+                json.to_string(),
+            )),
+        }
+    }
+
+    // parses optional path of control socket
+    fn parse_control_socket(json: &serde_json::Value) -> Result<Option<String>, ConfigError> {
+        json.get("control socket").map_or(Ok(None), |path| {
+            path.as_str()
+                .ok_or_else(|| {
+                    ConfigError::BadCommandConfig(String::from("control socket"), json.to_string())
+                })
+                .map(|path| Some(path.to_owned()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use serde_json::json;
+
+    use super::*;
+
+    // creates commands from name and dependencies pairs
+    fn create_commands(names: &[(&str, Vec<&str>)]) -> Vec<CommandConfig> {
+        names
+            .iter()
+            .map(|(name, depends_on)| {
+                CommandConfig::parse_config(&json!({
+                    "command": "ls",
+                    "name": name,
+                    "depends on": depends_on,
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_dependencies() {
+        let commands = create_commands(&[
+            ("server", vec!["db", "broker"]),
+            ("db", vec![]),
+            ("client", vec!["server"]),
+            ("broker", vec!["db"]),
+            ("tool", vec![]),
+        ]);
+        let dependencies = Config::resolve_dependencies(&commands).unwrap();
+        assert_eq!(
+            dependencies,
+            vec![vec![1, 3], vec![], vec![0], vec![1], vec![]]
+        );
+        assert_eq!(
+            Config::sort_dependencies(&commands, &dependencies).unwrap(),
+            vec![1, 3, 0, 2, 4]
+        );
+    }
+
+    #[test]
+    fn test_bad_dependencies() {
+        let commands = create_commands(&[
+            ("server", vec!["db"]),
+            ("db", vec!["server"]),
+            ("tool", vec![]),
+        ]);
+        let dependencies = Config::resolve_dependencies(&commands).unwrap();
+        match Config::sort_dependencies(&commands, &dependencies) {
+            Err(ConfigError::DependencyCycle(names)) => {
+                assert_eq!(names, vec![String::from("server"), String::from("db")])
+            }
+            _ => panic!("cycle not detected"),
+        }
+
+        let commands = create_commands(&[("server", vec!["server"])]);
+        let dependencies = Config::resolve_dependencies(&commands).unwrap();
+        Config::sort_dependencies(&commands, &dependencies).unwrap_err();
+
+        let commands = create_commands(&[("server", vec!["db"])]);
+        Config::resolve_dependencies(&commands).unwrap_err();
+
+        let commands = create_commands(&[("server", vec!["db"]), ("db", vec![]), ("db", vec![])]);
+        Config::resolve_dependencies(&commands).unwrap_err();
+    }
 }
@@ -30,6 +30,9 @@ pub(crate) enum ConfigError {
     WrongCommandsFormat,
     BadCommandConfig(String, String),
     UnsupportedSystem(TryFromIntError),
+    UnknownDependency(String, String),
+    AmbiguousDependency(String),
+    DependencyCycle(Vec<String>),
 }
 
 impl std::fmt::Display for ConfigError {
@@ -55,6 +58,19 @@ impl std::fmt::Display for ConfigError {
                 field, config
             ),
             ConfigError::UnsupportedSystem(_) => write!(f, "Unsupported operating system!"),
+            ConfigError::UnknownDependency(command, dependency) => write!(
+                f,
+                "Command {} depends on unknown command {}!",
+                command, dependency
+            ),
+            ConfigError::AmbiguousDependency(name) => write!(
+                f,
+                "Multiple commands are named {}, name them uniquely to depend on them!",
+                name
+            ),
+            ConfigError::DependencyCycle(names) => {
+                write!(f, "Dependency cycle between {}!", names.join(", "))
+            }
         }
     }
 }
@@ -0,0 +1,504 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use async_std::{
+    channel::{self, Receiver, Sender},
+    prelude::FutureExt,
+    task,
+};
+use chrono::Utc;
+
+use crate::{
+    command_log::CommandLog,
+    crash_store::CrashStore,
+    runner_error::{Result, RunnerError},
+    shutdown::Shutdown,
+    tui_state::{readiness_message, restart_message, TuiEvent},
+    ui_sender::UiSender,
+};
+
+// number of recent output lines kept for each command
+const TAIL_LINES: usize = 1000usize;
+
+// how often we check whether dependencies of a command are satisfied or command was stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// state of a command as reported to control clients
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum CommandState {
+    // waiting for previous commands or dependencies
+    Pending,
+
+    // supervised according to its mode
+    Running,
+
+    // stopped through control socket
+    Stopped,
+
+    // done according to its mode
+    Finished,
+}
+
+impl CommandState {
+    // name of the state used in control protocol
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            CommandState::Pending => "pending",
+            CommandState::Running => "running",
+            CommandState::Stopped => "stopped",
+            CommandState::Finished => "finished",
+        }
+    }
+}
+
+// control data of a single command
+struct CommandControl {
+    // command name
+    name: String,
+
+    // current state
+    state: CommandState,
+
+    // whether commands depending on this one can be started
+    satisfied: bool,
+
+    // recent output lines
+    output: VecDeque<String>,
+
+    // wakes up command waiting to be started again
+    start_tx: Sender<()>,
+    start_rx: Receiver<()>,
+}
+
+// Allows starting and stopping single commands while runner is running
+pub(crate) struct Control {
+    shutdown: Arc<Shutdown>,
+    crash_store: CrashStore,
+    commands: Mutex<Vec<CommandControl>>,
+}
+
+impl Control {
+    // creates control for given command names, all pending
+    pub(crate) fn new(
+        names: Vec<String>,
+        shutdown: Arc<Shutdown>,
+        crash_store: CrashStore,
+    ) -> Control {
+        Control {
+            shutdown,
+            crash_store,
+            commands: Mutex::new(
+                names
+                    .into_iter()
+                    .map(|name| {
+                        let (start_tx, start_rx) = channel::bounded(1);
+                        CommandControl {
+                            name,
+                            state: CommandState::Pending,
+                            satisfied: false,
+                            output: VecDeque::new(),
+                            start_tx,
+                            start_rx,
+                        }
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    // shutdown coordinator
+    pub(crate) fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    // storage of crash reports
+    pub(crate) fn crash_store(&self) -> &CrashStore {
+        &self.crash_store
+    }
+
+    // finds command id by name
+    pub(crate) fn find(&self, name: &str) -> Result<usize> {
+        self.lock()
+            .iter()
+            .position(|command| command.name == name)
+            .ok_or_else(|| RunnerError::ControlError(format!("No command named {}", name)))
+    }
+
+    // whether command should not be (re)started, because runner or command was stopped, or it is
+    // waiting to be started again after a restart request
+    pub(crate) fn should_stop(&self, id: usize) -> bool {
+        if self.shutdown.is_requested() {
+            return true;
+        }
+        let commands = self.lock();
+        commands[id].state == CommandState::Stopped || !commands[id].start_rx.is_empty()
+    }
+
+    // current state of a command
+    pub(crate) fn state(&self, id: usize) -> CommandState {
+        self.lock()[id].state
+    }
+
+    // marks command as supervised, unless it was stopped in the meantime
+    pub(crate) fn set_running(&self, id: usize) {
+        let mut commands = self.lock();
+        if commands[id].state != CommandState::Stopped {
+            commands[id].state = CommandState::Running;
+        }
+    }
+
+    // marks command as done, unless it was stopped in the meantime
+    pub(crate) fn set_finished(&self, id: usize) {
+        let mut commands = self.lock();
+        if commands[id].state != CommandState::Stopped {
+            commands[id].state = CommandState::Finished;
+        }
+    }
+
+    // marks command as satisfied, allowing commands depending on it to start
+    pub(crate) fn set_satisfied(&self, id: usize) {
+        self.lock()[id].satisfied = true;
+    }
+
+    // waits until all dependencies are satisfied. Returns false if runner is shutting down instead.
+    // Dependencies that ended without being satisfied are reported
+    pub(crate) async fn wait_for_dependencies(
+        &self,
+        id: usize,
+        dependencies: &[usize],
+        tx: &Sender<TuiEvent>,
+    ) -> Result<bool> {
+        let mut reported = false;
+        // dependencies reported as ended, reported again only if they end after another start
+        let mut reported_ended: Vec<usize> = Vec::new();
+        loop {
+            if self.shutdown.is_requested() {
+                return Ok(false);
+            }
+            // unsatisfied dependencies, with whether they ended already
+            let waiting: Vec<(usize, String, bool)> = {
+                let commands = self.lock();
+                dependencies
+                    .iter()
+                    .filter(|&&dependency| !commands[dependency].satisfied)
+                    .map(|&dependency| {
+                        let command = &commands[dependency];
+                        let ended = matches!(
+                            command.state,
+                            CommandState::Stopped | CommandState::Finished
+                        );
+                        (dependency, command.name.clone(), ended)
+                    })
+                    .collect()
+            };
+            if waiting.is_empty() {
+                return Ok(true);
+            }
+            if !reported {
+                let names: Vec<&str> = waiting.iter().map(|(_, name, _)| name.as_str()).collect();
+                tx.send(TuiEvent::NewSystemMessage(
+                    id,
+                    format!("Waiting for {}", names.join(", ")),
+                ))
+                .await?;
+                reported = true;
+            }
+            // dependency that was stopped or gave up without being satisfied will not start on
+            // its own, only through control socket
+            reported_ended.retain(|reported| {
+                waiting
+                    .iter()
+                    .any(|(dependency, _, ended)| dependency == reported && *ended)
+            });
+            for (dependency, name, ended) in &waiting {
+                if *ended && !reported_ended.contains(dependency) {
+                    tx.send(TuiEvent::NewSystemMessage(
+                        id,
+                        format!(
+                            "{} ended without becoming ready, waiting until it is started again",
+                            name
+                        ),
+                    ))
+                    .await?;
+                    reported_ended.push(*dependency);
+                }
+            }
+            task::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    // sleeps for given time, waking up early if command should stop or restart. Returns false in
+    // that case
+    pub(crate) async fn sleep(&self, id: usize, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        while !self.should_stop(id) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return true;
+            }
+            task::sleep(remaining.min(POLL_INTERVAL)).await;
+        }
+        false
+    }
+
+    // name, state and process id of every command
+    pub(crate) fn list(&self) -> Vec<(String, CommandState, Option<u32>)> {
+        self.lock()
+            .iter()
+            .enumerate()
+            .map(|(id, command)| (command.name.clone(), command.state, self.shutdown.pid(id)))
+            .collect()
+    }
+
+    // stores output line of a command
+    pub(crate) fn record(&self, id: usize, stream: &str, line: &str) {
+        let mut commands = self.lock();
+        if let Some(command) = commands.get_mut(id) {
+            command.output.push_back(format!(
+                "{} {} | {}",
+                Utc::now().format("%H:%M:%S"),
+                stream,
+                line
+            ));
+            while command.output.len() > TAIL_LINES {
+                command.output.pop_front();
+            }
+        }
+    }
+
+    // returns up to given number of most recent output lines of a command
+    pub(crate) fn tail(&self, id: usize, lines: usize) -> Vec<String> {
+        let commands = self.lock();
+        let output = &commands[id].output;
+        output
+            .iter()
+            .skip(output.len().saturating_sub(lines))
+            .cloned()
+            .collect()
+    }
+
+    // waits until command is started again. Returns false if runner is shutting down instead
+    pub(crate) async fn wait_for_start(&self, id: usize) -> bool {
+        let start_rx = self.lock()[id].start_rx.clone();
+        let started = async { start_rx.recv().await.is_ok() };
+        let shutdown = async {
+            self.shutdown.wait_for_request().await;
+            false
+        };
+        started.race(shutdown).await && !self.shutdown.is_requested()
+    }
+
+    // starts stopped or finished command
+    pub(crate) fn start(&self, id: usize) -> Result<()> {
+        let mut commands = self.lock();
+        match commands[id].state {
+            CommandState::Pending => Err(RunnerError::ControlError(String::from(
+                "Command is waiting for previous commands",
+            ))),
+            CommandState::Running => Err(RunnerError::ControlError(String::from(
+                "Command is already running",
+            ))),
+            CommandState::Stopped | CommandState::Finished => {
+                commands[id].state = CommandState::Running;
+                // channel can only be full if start is already pending
+                let _ = commands[id].start_tx.try_send(());
+                Ok(())
+            }
+        }
+    }
+
+    // stops command, preventing it from being restarted until it is started again
+    pub(crate) async fn stop(&self, id: usize, tx: &Sender<TuiEvent>) -> Result<()> {
+        {
+            let mut commands = self.lock();
+            match commands[id].state {
+                CommandState::Pending | CommandState::Running => {
+                    commands[id].state = CommandState::Stopped
+                }
+                CommandState::Stopped | CommandState::Finished => {
+                    return Err(RunnerError::ControlError(String::from(
+                        "Command is not running",
+                    )))
+                }
+            }
+        }
+        self.shutdown.stop_command(id, tx).await
+    }
+
+    // stops command if it is running and starts it again. Pending start interrupts restart delay
+    // of the command, which then starts again from scratch
+    pub(crate) async fn restart(&self, id: usize, tx: &Sender<TuiEvent>) -> Result<()> {
+        if self.state(id) == CommandState::Running {
+            self.stop(id, tx).await?;
+        }
+        self.start(id)
+    }
+
+    // locks commands
+    fn lock(&self) -> MutexGuard<'_, Vec<CommandControl>> {
+        crate::lock::lock(&self.commands)
+    }
+}
+
+#[cfg(test)]
+impl Control {
+    // creates control for independent commands, with crash reports stored in temporary folder
+    pub(crate) fn for_test(names: &[&str]) -> Control {
+        let config = crate::config::Config {
+            commands: Vec::new(),
+            crash_path: std::env::temp_dir().to_string_lossy().into_owned(),
+            crash_retention: Default::default(),
+            crash_archive: None,
+            control_socket: None,
+            dependencies: vec![vec![]; names.len()],
+            start_order: (0..names.len()).collect(),
+        };
+        Control::new(
+            names.iter().map(|name| name.to_string()).collect(),
+            Arc::new(Shutdown::new(&config.start_order)),
+            CrashStore::new(&config),
+        )
+    }
+}
+
+// records output of commands for control clients and command logs, and passes events on to the
+// interface. Output lines lossy interface can not keep up with are dropped only from the interface.
+// Logs are flushed and dropped lines reported whenever there are no more events waiting, and
+// closed before exit is passed on
+pub(crate) async fn forward_events(
+    rx: Receiver<TuiEvent>,
+    ui_tx: Sender<TuiEvent>,
+    control: Arc<Control>,
+    mut logs: Vec<Option<CommandLog>>,
+    lossy: bool,
+) -> Result<()> {
+    let mut ui_tx = UiSender::new(ui_tx, lossy);
+    while let Ok(event) = rx.recv().await {
+        match &event {
+            TuiEvent::CommandStarted(id, _, _) => control.record(*id, "sys", "Command Started"),
+            TuiEvent::NewStdoutMessage(id, line) => {
+                control.record(*id, "out", line);
+                write_log(&mut logs, *id, &mut ui_tx, |log| {
+                    log.write(false, line, Utc::now())
+                })
+                .await?;
+            }
+            TuiEvent::NewStderrMessage(id, line) => {
+                control.record(*id, "err", line);
+                write_log(&mut logs, *id, &mut ui_tx, |log| {
+                    log.write(true, line, Utc::now())
+                })
+                .await?;
+            }
+            TuiEvent::CommandEnded(id, _, _) => control.record(*id, "sys", "Command ended"),
+            TuiEvent::NewSystemMessage(id, line) => control.record(*id, "sys", line),
+            TuiEvent::ReadinessChanged(id, ready) => {
+                control.record(*id, "sys", &readiness_message(*ready))
+            }
+            TuiEvent::RestartScheduled(id, delay) => {
+                control.record(*id, "sys", &restart_message(*delay))
+            }
+            TuiEvent::Exit => {
+                // runner exits once interface does, so logs are closed before it is told to
+                for id in 0..logs.len() {
+                    write_log(&mut logs, id, &mut ui_tx, |log| log.flush()).await?;
+                }
+                logs.clear();
+            }
+            TuiEvent::TabListChanged(_)
+            | TuiEvent::StateChanged(_, _)
+            | TuiEvent::ResourcesSampled(_, _)
+            | TuiEvent::Input(_) => {}
+        }
+        ui_tx.send(event).await?;
+        if rx.is_empty() {
+            for id in 0..logs.len() {
+                write_log(&mut logs, id, &mut ui_tx, |log| log.flush()).await?;
+            }
+            ui_tx.report_dropped().await?;
+        }
+    }
+    Ok(())
+}
+
+// writes to log of a command, if it has one. Log that can not be written is reported and
+// disabled, as logging should never stop supervision
+async fn write_log(
+    logs: &mut [Option<CommandLog>],
+    id: usize,
+    ui_tx: &mut UiSender,
+    write: impl FnOnce(&mut CommandLog) -> std::io::Result<()>,
+) -> Result<()> {
+    let log = match logs.get_mut(id) {
+        Some(log) => log,
+        None => return Ok(()),
+    };
+    if let Some(Err(err)) = log.as_mut().map(write) {
+        *log = None;
+        ui_tx
+            .send(TuiEvent::NewSystemMessage(
+                id,
+                format!("Cannot write log, logging stopped - {}", err),
+            ))
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_dependency_gave_up() {
+        let control = Control::for_test(&["db", "server"]);
+        let (tx, rx) = channel::unbounded();
+        // supervision of dependency ended without it ever being satisfied
+        control.set_running(0);
+        control.set_finished(0);
+
+        let waited = task::block_on(
+            control
+                .wait_for_dependencies(1, &[0], &tx)
+                .timeout(POLL_INTERVAL * 3),
+        );
+        assert!(waited.is_err());
+        let messages: Vec<String> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|event| match event {
+                TuiEvent::NewSystemMessage(1, message) => Some(message),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                String::from("Waiting for db"),
+                String::from("db ended without becoming ready, waiting until it is started again"),
+            ]
+        );
+
+        control.set_satisfied(0);
+        assert!(task::block_on(control.wait_for_dependencies(1, &[0], &tx)).unwrap());
+    }
+}
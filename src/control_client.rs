@@ -0,0 +1,118 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+};
+
+use crate::runner_error::{Result, RunnerError};
+
+// sends request to control socket of a running runner and prints the response
+pub(crate) fn run(socket: &str, request: serde_json::Value) -> Result<()> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(format!("{}\n", request).as_bytes())?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    let response: serde_json::Value = serde_json::from_str(&response)
+        .map_err(|err| RunnerError::ControlError(format!("Bad response - {}", err)))?;
+
+    if response.get("ok").and_then(|ok| ok.as_bool()) != Some(true) {
+        return Err(RunnerError::ControlError(
+            response
+                .get("error")
+                .and_then(|err| err.as_str())
+                .unwrap_or("Unknown error")
+                .to_owned(),
+        ));
+    }
+    for line in format_response(&response) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+// formats successful response for printing
+fn format_response(response: &serde_json::Value) -> Vec<String> {
+    if let Some(commands) = response.get("commands").and_then(|c| c.as_array()) {
+        let width = commands
+            .iter()
+            .filter_map(|command| command.get("name").and_then(|name| name.as_str()))
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0);
+        return commands
+            .iter()
+            .map(|command| {
+                format!(
+                    "{:width$} {:8} {}",
+                    command.get("name").and_then(|v| v.as_str()).unwrap_or("?"),
+                    command.get("state").and_then(|v| v.as_str()).unwrap_or("?"),
+                    command
+                        .get("pid")
+                        .and_then(|v| v.as_u64())
+                        .map_or_else(|| String::from("-"), |pid| pid.to_string()),
+                    width = width
+                )
+            })
+            .collect();
+    }
+    if let Some(lines) = response.get("lines").and_then(|l| l.as_array()) {
+        return lines
+            .iter()
+            .filter_map(|line| line.as_str())
+            .map(|line| line.to_owned())
+            .collect();
+    }
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_format_response() {
+        let response = json!({
+            "ok": true,
+            "commands": [
+                { "name": "db", "state": "running", "pid": 42 },
+                { "name": "server", "state": "stopped", "pid": null },
+            ]
+        });
+        assert_eq!(
+            format_response(&response),
+            vec![
+                String::from("db     running  42"),
+                String::from("server stopped  -")
+            ]
+        );
+
+        let response = json!({ "ok": true, "lines": ["a", "b"] });
+        assert_eq!(
+            format_response(&response),
+            vec![String::from("a"), String::from("b")]
+        );
+
+        let response = json!({ "ok": true });
+        assert!(format_response(&response).is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::sync::Arc;
+
+use async_std::{
+    channel::Sender,
+    io::BufReader,
+    os::unix::net::{UnixListener, UnixStream},
+    prelude::*,
+    task,
+};
+use serde_json::json;
+
+use crate::{
+    control::Control,
+    runner_error::{Result, RunnerError},
+    tui_state::TuiEvent,
+};
+
+// default number of lines returned by tail request
+const DEFAULT_TAIL_LINES: usize = 20usize;
+
+// binds control socket, so that failing to bind is reported before runner starts any commands
+pub(crate) async fn bind(path: &str) -> Result<UnixListener> {
+    // socket left behind by previous run would prevent binding
+    let _ = std::fs::remove_file(path);
+    Ok(UnixListener::bind(path).await?)
+}
+
+// accepts control clients on a bound unix socket. Each connection sends one json request line and
+// receives one json response line
+pub(crate) async fn run(
+    listener: UnixListener,
+    control: Arc<Control>,
+    tx: Sender<TuiEvent>,
+) -> Result<()> {
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        task::spawn(handle_connection(stream?, control.clone(), tx.clone()));
+    }
+    Ok(())
+}
+
+// reads single request and writes response
+async fn handle_connection(
+    stream: UnixStream,
+    control: Arc<Control>,
+    tx: Sender<TuiEvent>,
+) -> Result<()> {
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request).await?;
+    let response = match handle_request(&request, &control, &tx).await {
+        Ok(response) => response,
+        Err(RunnerError::ControlError(err)) => json!({ "ok": false, "error": err }),
+        Err(err) => json!({ "ok": false, "error": err.to_string() }),
+    };
+    (&stream)
+        .write_all(format!("{}\n", response).as_bytes())
+        .await?;
+    Ok(())
+}
+
+// parses request and executes it
+async fn handle_request(
+    request: &str,
+    control: &Control,
+    tx: &Sender<TuiEvent>,
+) -> Result<serde_json::Value> {
+    let request: serde_json::Value = serde_json::from_str(request)
+        .map_err(|err| RunnerError::ControlError(format!("Bad request - {}", err)))?;
+    let command = request
+        .get("command")
+        .and_then(|command| command.as_str())
+        .ok_or_else(|| RunnerError::ControlError(String::from("Missing request command")))?;
+
+    match command {
+        "list" => Ok(json!({
+            "ok": true,
+            "commands": control
+                .list()
+                .into_iter()
+                .map(|(name, state, pid)| json!({ "name": name, "state": state.as_str(), "pid": pid }))
+                .collect::<Vec<_>>(),
+        })),
+        "start" => {
+            control.start(find_command(&request, control)?)?;
+            Ok(json!({ "ok": true }))
+        }
+        "stop" => {
+            control.stop(find_command(&request, control)?, tx).await?;
+            Ok(json!({ "ok": true }))
+        }
+        "restart" => {
+            control
+                .restart(find_command(&request, control)?, tx)
+                .await?;
+            Ok(json!({ "ok": true }))
+        }
+        "tail" => {
+            let lines = request
+                .get("lines")
+                .and_then(|lines| lines.as_u64())
+                .map_or(DEFAULT_TAIL_LINES, |lines| lines as usize);
+            Ok(json!({
+                "ok": true,
+                "lines": control.tail(find_command(&request, control)?, lines),
+            }))
+        }
+        _ => Err(RunnerError::ControlError(format!(
+            "Unknown request command {}",
+            command
+        ))),
+    }
+}
+
+// finds command id from name in request
+fn find_command(request: &serde_json::Value, control: &Control) -> Result<usize> {
+    let name = request
+        .get("name")
+        .and_then(|name| name.as_str())
+        .ok_or_else(|| RunnerError::ControlError(String::from("Missing command name")))?;
+    control.find(name)
+}
@@ -0,0 +1,471 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use async_std::task;
+use chrono::{NaiveDateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::{
+    command_config::CrashRetention,
+    config::{Config, CrashArchive},
+    lock::lock,
+    runner_error::Result,
+};
+
+// format of timestamp at the end of crash folder names
+const FOLDER_TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H:%M:%S";
+
+// length of "-<timestamp>" suffix of crash folder names
+const FOLDER_SUFFIX_LEN: usize = 20usize;
+
+// crash folder or archive found in crash path
+struct CrashFolder {
+    path: PathBuf,
+
+    // name of command that wrote it
+    name: String,
+
+    // start of the process
+    started: NaiveDateTime,
+
+    // total size of files in bytes
+    size: u64,
+}
+
+// manages crash reports in crash path. Keeps crash path within configured limits, where command
+// limits apply to folders of that command, while limits of entire configuration apply to all
+// folders. Archives are treated the same as folders. Folders of runs that did not end yet are
+// never deleted
+#[derive(Clone)]
+pub(crate) struct CrashStore {
+    path: String,
+    archive: Option<CrashArchive>,
+    global: CrashRetention,
+    commands: Vec<(String, CrashRetention)>,
+    live: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+// keeps crash folder of a run out of cleanup until dropped
+pub(crate) struct LiveFolder<'a> {
+    store: &'a CrashStore,
+    path: PathBuf,
+}
+
+impl Drop for LiveFolder<'_> {
+    fn drop(&mut self) {
+        let mut live = lock(&self.store.live);
+        if let Some(index) = live.iter().position(|path| path == &self.path) {
+            live.remove(index);
+        }
+    }
+}
+
+impl CrashStore {
+    // creates crash store from configuration
+    pub(crate) fn new(config: &Config) -> CrashStore {
+        CrashStore {
+            path: config.crash_path.clone(),
+            archive: config.crash_archive,
+            global: config.crash_retention.clone(),
+            commands: config
+                .commands
+                .iter()
+                .filter(|command| command.crash_retention != CrashRetention::default())
+                .map(|command| (command.name.clone(), command.crash_retention.clone()))
+                .collect(),
+            live: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    // marks crash folder of a started run as live until returned guard is dropped
+    pub(crate) fn live(&self, folder: &str) -> LiveFolder<'_> {
+        let path = PathBuf::from(folder);
+        lock(&self.live).push(path.clone());
+        LiveFolder { store: self, path }
+    }
+
+    // deletes oldest crash folders over limits in the background
+    pub(crate) async fn clean(&self) {
+        if self.global == CrashRetention::default() && self.commands.is_empty() {
+            return;
+        }
+        let store = self.clone();
+        task::spawn_blocking(move || store.clean_folders()).await;
+    }
+
+    // deletes oldest crash folders of ended runs over limits. Folders that can not be read or
+    // deleted are skipped, as cleanup should never stop supervision
+    fn clean_folders(&self) {
+        let mut folders = scan(Path::new(&self.path));
+        {
+            let live = lock(&self.live);
+            folders.retain(|folder| !live.contains(&folder.path));
+        }
+        let now = Utc::now().naive_utc();
+        for (name, limits) in &self.commands {
+            let own: Vec<&CrashFolder> = folders.iter().filter(|f| &f.name == name).collect();
+            let expired: Vec<PathBuf> = select_expired(&summarize(&own), limits, now)
+                .into_iter()
+                .map(|index| own[index].path.clone())
+                .collect();
+            remove(&mut folders, &expired);
+        }
+        let all: Vec<&CrashFolder> = folders.iter().collect();
+        let expired: Vec<PathBuf> = select_expired(&summarize(&all), &self.global, now)
+            .into_iter()
+            .map(|index| all[index].path.clone())
+            .collect();
+        remove(&mut folders, &expired);
+    }
+
+    // packs crash folder into single archive, if configured, and removes the folder
+    pub(crate) async fn archive(&self, folder: &str) -> Result<()> {
+        let format = match self.archive {
+            Some(format) => format,
+            None => return Ok(()),
+        };
+        let folder = PathBuf::from(folder);
+        task::spawn_blocking(move || archive_folder(&folder, format)).await?;
+        Ok(())
+    }
+}
+
+// writes archive next to the folder and removes the folder. Archive is written under temporary
+// name first, so incomplete archives are never left behind under the final name
+fn archive_folder(folder: &Path, format: CrashArchive) -> io::Result<()> {
+    let name = folder
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Bad crash folder"))?
+        .to_string_lossy()
+        .into_owned();
+    let archive_name = reserve_archive(folder, &name, format)?;
+    let archive = folder.with_file_name(&archive_name);
+    let partial = folder.with_file_name(format!("{}.part", archive_name));
+    if let Err(err) = write_archive(folder, &name, &partial, format) {
+        let _ = std::fs::remove_file(&partial);
+        let _ = std::fs::remove_file(&archive);
+        return Err(err);
+    }
+    std::fs::rename(&partial, &archive)?;
+    std::fs::remove_dir_all(folder)
+}
+
+// creates empty archive next to the folder, so that no other archive takes its name, and returns
+// its name. Processes started within the same second share folder name, so later archives of such
+// folders are numbered
+fn reserve_archive(folder: &Path, name: &str, format: CrashArchive) -> io::Result<String> {
+    let mut number = 0u32;
+    loop {
+        let archive_name = match number {
+            0 => format!("{}.{}", name, format.extension()),
+            _ => format!("{}.{}.{}", name, number, format.extension()),
+        };
+        let reserved = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(folder.with_file_name(&archive_name));
+        match reserved {
+            Ok(_) => return Ok(archive_name),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => number += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// writes folder into archive of given format
+fn write_archive(folder: &Path, name: &str, path: &Path, format: CrashArchive) -> io::Result<()> {
+    let file = File::create(path)?;
+    match format {
+        CrashArchive::TarGz => {
+            let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+            builder.append_dir_all(name, folder)?;
+            builder.into_inner()?.finish()?;
+        }
+        CrashArchive::Zip => {
+            let mut zip = ZipWriter::new(file);
+            for entry in std::fs::read_dir(folder)? {
+                let entry = entry?;
+                if !entry.path().is_file() {
+                    continue;
+                }
+                zip.start_file(
+                    format!("{}/{}", name, entry.file_name().to_string_lossy()),
+                    FileOptions::default(),
+                )?;
+                io::copy(&mut File::open(entry.path())?, &mut zip)?;
+            }
+            zip.finish()?;
+        }
+    }
+    Ok(())
+}
+
+// finds crash folders and archives in crash path, oldest first
+fn scan(path: &Path) -> Vec<CrashFolder> {
+    let mut folders: Vec<CrashFolder> = match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|entry| {
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                let is_dir = entry.path().is_dir();
+                let folder = if is_dir {
+                    file_name.as_str()
+                } else {
+                    let archive =
+                        [CrashArchive::TarGz, CrashArchive::Zip]
+                            .iter()
+                            .find_map(|format| {
+                                file_name.strip_suffix(&format!(".{}", format.extension()))
+                            })?;
+                    // archives sharing folder name are numbered
+                    archive
+                        .rsplit_once('.')
+                        .filter(|(_, number)| number.parse::<u32>().is_ok())
+                        .map_or(archive, |(folder, _)| folder)
+                };
+                let (name, started) = parse_folder_name(folder)?;
+                Some(CrashFolder {
+                    size: if is_dir {
+                        folder_size(&entry.path())
+                    } else {
+                        entry.metadata().map_or(0, |metadata| metadata.len())
+                    },
+                    path: entry.path(),
+                    name,
+                    started,
+                })
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    folders.sort_by(|a, b| a.started.cmp(&b.started).then_with(|| a.path.cmp(&b.path)));
+    folders
+}
+
+// splits "<name>-<timestamp>" folder name. Returns None for folders not written by runner
+fn parse_folder_name(folder: &str) -> Option<(String, NaiveDateTime)> {
+    let split = folder.len().checked_sub(FOLDER_SUFFIX_LEN)?;
+    let (name, suffix) = (folder.get(..split)?, folder.get(split..)?);
+    let timestamp = suffix.strip_prefix('-')?;
+    let started = NaiveDateTime::parse_from_str(timestamp, FOLDER_TIMESTAMP_FORMAT).ok()?;
+    Some((name.to_owned(), started))
+}
+
+// total size of files in a folder
+fn folder_size(path: &Path) -> u64 {
+    std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| match entry.metadata() {
+                    Ok(metadata) if metadata.is_dir() => folder_size(&entry.path()),
+                    Ok(metadata) => metadata.len(),
+                    Err(_) => 0,
+                })
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+// start times and sizes of folders
+fn summarize(folders: &[&CrashFolder]) -> Vec<(NaiveDateTime, u64)> {
+    folders
+        .iter()
+        .map(|folder| (folder.started, folder.size))
+        .collect()
+}
+
+// deletes folders or archives and forgets them
+fn remove(folders: &mut Vec<CrashFolder>, expired: &[PathBuf]) {
+    for path in expired {
+        let _ = if path.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        };
+    }
+    folders.retain(|folder| !expired.contains(&folder.path));
+}
+
+// selects indexes of folders to delete, given start times and sizes of folders ordered oldest
+// first. Folders over max age go first, then oldest folders until count and size fit the limits
+fn select_expired(
+    folders: &[(NaiveDateTime, u64)],
+    limits: &CrashRetention,
+    now: NaiveDateTime,
+) -> Vec<usize> {
+    let mut first_kept = match limits.max_age {
+        Some(max_age) => folders
+            .iter()
+            .position(|(started, _)| *started > now - max_age)
+            .unwrap_or(folders.len()),
+        None => 0,
+    };
+    if let Some(max_folders) = limits.max_folders {
+        first_kept = first_kept.max(folders.len().saturating_sub(max_folders));
+    }
+    if let Some(max_size) = limits.max_size {
+        let mut size: u64 = folders[first_kept..].iter().map(|(_, size)| size).sum();
+        while size > max_size && first_kept < folders.len() {
+            size -= folders[first_kept].1;
+            first_kept += 1;
+        }
+    }
+    (0..first_kept).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn folders(now: NaiveDateTime, ages_and_sizes: &[(i64, u64)]) -> Vec<(NaiveDateTime, u64)> {
+        ages_and_sizes
+            .iter()
+            .map(|(age, size)| (now - chrono::Duration::hours(*age), *size))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_folder_name() {
+        let (name, started) = parse_folder_name("my-server-2021-06-01_12:30:05").unwrap();
+        assert_eq!(name, "my-server");
+        assert_eq!(
+            started.format(FOLDER_TIMESTAMP_FORMAT).to_string(),
+            "2021-06-01_12:30:05"
+        );
+        assert!(parse_folder_name("notes").is_none());
+        assert!(parse_folder_name("server_2021-06-01_12:30:05").is_none());
+    }
+
+    #[test]
+    fn test_select_expired() {
+        let now = Utc::now().naive_utc();
+        let folders = folders(now, &[(50, 10), (30, 10), (20, 30), (10, 10), (1, 10)]);
+
+        assert!(select_expired(&folders, &CrashRetention::default(), now).is_empty());
+
+        let limits = CrashRetention {
+            max_age: Some(chrono::Duration::hours(24)),
+            ..Default::default()
+        };
+        assert_eq!(select_expired(&folders, &limits, now), vec![0, 1]);
+
+        let limits = CrashRetention {
+            max_folders: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(select_expired(&folders, &limits, now), vec![0, 1, 2]);
+
+        let limits = CrashRetention {
+            max_size: Some(50),
+            ..Default::default()
+        };
+        assert_eq!(select_expired(&folders, &limits, now), vec![0, 1]);
+
+        let limits = CrashRetention {
+            max_folders: Some(4),
+            max_age: Some(chrono::Duration::hours(100)),
+            max_size: Some(5),
+        };
+        assert_eq!(select_expired(&folders, &limits, now), vec![0, 1, 2, 3, 4]);
+    }
+    #[test]
+    fn test_archive_folder() {
+        let root = TestDir::new("archive");
+        for format in [CrashArchive::TarGz, CrashArchive::Zip] {
+            let folder = root.join("server-2021-06-01_12:30:05");
+            std::fs::create_dir_all(&folder).unwrap();
+            std::fs::write(folder.join("stdout.txt"), "12:30:05 | hello\n").unwrap();
+            std::fs::write(folder.join("crash.json"), "{}\n").unwrap();
+
+            archive_folder(&folder, format).unwrap();
+            assert!(!folder.exists());
+            let archive = root.join(format!("server-2021-06-01_12:30:05.{}", format.extension()));
+            assert!(archive.metadata().unwrap().len() > 0);
+
+            let scanned = scan(root.path());
+            assert_eq!(scanned.len(), 1);
+            assert_eq!(scanned[0].name, "server");
+            std::fs::remove_file(archive).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_clean_skips_live_folders() {
+        let root = TestDir::new("live");
+        let config = Config {
+            crash_path: root.path().to_string_lossy().into_owned(),
+            crash_retention: CrashRetention {
+                max_folders: Some(0),
+                ..Default::default()
+            },
+            commands: Vec::new(),
+            crash_archive: None,
+            control_socket: None,
+            dependencies: Vec::new(),
+            start_order: Vec::new(),
+        };
+        let store = CrashStore::new(&config);
+        let ended = root.join("server-2021-06-01_12:30:05");
+        let running = root.join("server-2021-06-01_12:40:05");
+        for folder in [&ended, &running] {
+            std::fs::create_dir_all(folder).unwrap();
+            std::fs::write(folder.join("stderr.txt"), "error\n").unwrap();
+        }
+
+        let live = store.live(&running.to_string_lossy());
+        task::block_on(store.clean());
+        assert!(!ended.exists());
+        assert!(running.exists());
+
+        drop(live);
+        task::block_on(store.clean());
+        assert!(!running.exists());
+    }
+
+    #[test]
+    fn test_archive_same_folder_name() {
+        let root = TestDir::new("same-name");
+        let folder = root.join("server-2021-06-01_12:30:05");
+        for crash in ["first", "second", "third"] {
+            std::fs::create_dir_all(&folder).unwrap();
+            std::fs::write(folder.join("stdout.txt"), crash).unwrap();
+            archive_folder(&folder, CrashArchive::Zip).unwrap();
+        }
+        for archive in [
+            "server-2021-06-01_12:30:05.zip",
+            "server-2021-06-01_12:30:05.1.zip",
+            "server-2021-06-01_12:30:05.2.zip",
+        ] {
+            assert!(root.join(archive).metadata().unwrap().len() > 0);
+        }
+
+        let scanned = scan(root.path());
+        assert_eq!(scanned.len(), 3);
+        assert!(scanned.iter().all(|folder| folder.name == "server"));
+    }
+}
@@ -0,0 +1,188 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+
+use crate::command_config::BackupStrategy;
+
+// source of current time, replaceable in tests
+pub(crate) trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+// wall clock
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+// counts crashes in a sliding window of backup strategy period. Once there are more crashes in the
+// window than backup strategy allows, the limit is reported once and the window starts over, so
+// next report needs as many new crashes again
+pub(crate) struct CrashWindow<C: Clock = SystemClock> {
+    // crashes allowed in period
+    times: u64,
+
+    // length of the window
+    period: chrono::Duration,
+
+    // crashes inside the window, oldest first
+    crashes: VecDeque<DateTime<Utc>>,
+
+    clock: C,
+}
+
+impl CrashWindow {
+    // creates empty window for backup strategy stage
+    pub(crate) fn new(strategy: &BackupStrategy) -> CrashWindow {
+        CrashWindow::with_clock(strategy.times, strategy.period, SystemClock)
+    }
+}
+
+impl<C: Clock> CrashWindow<C> {
+    // creates empty window using given clock
+    pub(crate) fn with_clock(times: u64, period: chrono::Duration, clock: C) -> CrashWindow<C> {
+        CrashWindow {
+            times,
+            period,
+            crashes: VecDeque::new(),
+            clock,
+        }
+    }
+
+    // records a crash. Returns true when it pushed number of crashes in the window over the limit
+    pub(crate) fn record(&mut self) -> bool {
+        let now = self.clock.now();
+        self.evict(now);
+        self.crashes.push_back(now);
+        if self.crashes.len() as u64 > self.times {
+            self.reset();
+            return true;
+        }
+        false
+    }
+
+    // number of crashes currently in the window
+    pub(crate) fn count(&mut self) -> usize {
+        let now = self.clock.now();
+        self.evict(now);
+        self.crashes.len()
+    }
+
+    // forgets all crashes
+    fn reset(&mut self) {
+        self.crashes.clear();
+    }
+
+    // drops crashes older than period
+    fn evict(&mut self, now: DateTime<Utc>) {
+        while let Some(&oldest) = self.crashes.front() {
+            if oldest > now - self.period {
+                break;
+            }
+            self.crashes.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    // clock moved by hand
+    #[derive(Clone)]
+    struct TestClock(Rc<Cell<DateTime<Utc>>>);
+
+    impl TestClock {
+        fn advance(&self, seconds: i64) {
+            self.0
+                .set(self.0.get() + chrono::Duration::seconds(seconds));
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.0.get()
+        }
+    }
+
+    fn window(times: u64, period: i64) -> (CrashWindow<TestClock>, TestClock) {
+        let clock = TestClock(Rc::new(Cell::new(Utc::now())));
+        (
+            CrashWindow::with_clock(times, chrono::Duration::seconds(period), clock.clone()),
+            clock,
+        )
+    }
+
+    #[test]
+    fn test_limit_reported_once_per_crossing() {
+        let (mut window, clock) = window(2, 60);
+        assert!(!window.record());
+        clock.advance(1);
+        assert!(!window.record());
+        clock.advance(1);
+        assert!(window.record());
+
+        // window starts over after limit is reached
+        clock.advance(1);
+        assert!(!window.record());
+        assert!(!window.record());
+        assert!(window.record());
+    }
+
+    #[test]
+    fn test_old_crashes_evicted() {
+        let (mut window, clock) = window(2, 60);
+        assert!(!window.record());
+        clock.advance(30);
+        assert!(!window.record());
+        assert_eq!(window.count(), 2);
+        clock.advance(30);
+        // first crash is exactly one period old and no longer counts
+        assert_eq!(window.count(), 1);
+        assert!(!window.record());
+        clock.advance(100);
+        assert_eq!(window.count(), 0);
+        assert!(!window.record());
+        assert!(!window.record());
+        assert!(window.record());
+    }
+
+    #[test]
+    fn test_zero_times() {
+        let (mut window, _) = window(0, 60);
+        assert!(window.record());
+        assert!(window.record());
+    }
+
+    #[test]
+    fn test_reset() {
+        let (mut window, _) = window(1, 60);
+        assert!(!window.record());
+        window.reset();
+        assert!(!window.record());
+        assert!(window.record());
+    }
+}
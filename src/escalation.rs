@@ -0,0 +1,223 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::time::Duration;
+
+use crate::{
+    command_config::{BackupStrategy, CommandConfig},
+    crash_window::CrashWindow,
+};
+
+// what runner should do after a crash
+pub(crate) enum Escalation<'a> {
+    // restart as usual
+    Restart,
+
+    // crash limit of a stage was exceeded, run its actions. Contains stage number, starting with 1
+    Stage(usize, &'a BackupStrategy),
+}
+
+// walks through backup strategy stages as command keeps crashing. Each stage counts crashes in its
+// own window, starting once previous stage was reached. Last stage repeats
+pub(crate) struct EscalationLadder<'a> {
+    command: &'a CommandConfig,
+
+    // index of stage whose crash limit is counted
+    counted: usize,
+
+    // number of last stage reached, 0 before first escalation
+    reached: usize,
+
+    // crashes counted for current stage
+    window: Option<CrashWindow>,
+
+    // command used instead of configured one after fallback stage was reached
+    fallback: Option<CommandConfig>,
+}
+
+impl<'a> EscalationLadder<'a> {
+    // creates ladder at first stage
+    pub(crate) fn new(command: &'a CommandConfig) -> EscalationLadder<'a> {
+        EscalationLadder {
+            command,
+            counted: 0,
+            reached: 0,
+            window: command.backup_strategy.first().map(CrashWindow::new),
+            fallback: None,
+        }
+    }
+
+    // command to run next, either configured one or fallback
+    pub(crate) fn command(&self) -> &CommandConfig {
+        self.fallback.as_ref().unwrap_or(self.command)
+    }
+
+    // number of last reached stage, 0 if command was not escalated
+    pub(crate) fn stage(&self) -> usize {
+        self.reached
+    }
+
+    // number of crashes counted towards crash limit of current stage
+    pub(crate) fn recent_crashes(&mut self) -> usize {
+        self.window.as_mut().map_or(0, |window| window.count())
+    }
+
+    // records a crash, escalating once crash limit of current stage is exceeded
+    pub(crate) fn record_crash(&mut self) -> Escalation<'a> {
+        let stages = &self.command.backup_strategy;
+        let window = match &mut self.window {
+            Some(window) => window,
+            None => return Escalation::Restart,
+        };
+        if !window.record() {
+            return Escalation::Restart;
+        }
+        let reached = self.counted;
+        self.reached = reached + 1;
+        let stage = &stages[reached];
+        if let Some((command, args)) = &stage.fallback {
+            self.fallback = Some(CommandConfig {
+                command: command.clone(),
+                args: args.clone(),
+                ..self.command.clone()
+            });
+        }
+        if reached + 1 < stages.len() {
+            self.counted = reached + 1;
+            self.window = Some(CrashWindow::new(&stages[self.counted]));
+        }
+        Escalation::Stage(reached + 1, stage)
+    }
+
+    // goes back to first stage and configured command once process stayed up for stable period.
+    // Returns true if ladder was escalated before
+    pub(crate) fn ran_for(&mut self, uptime: Duration) -> bool {
+        let stable_period = self
+            .command
+            .restart
+            .stable_period
+            .to_std()
+            .unwrap_or(Duration::ZERO);
+        if uptime < stable_period || self.reached == 0 {
+            return false;
+        }
+        *self = EscalationLadder::new(self.command);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::command_config::CommandMode;
+
+    #[test]
+    fn test_escalation() {
+        let command = CommandConfig::parse_config(&json!({
+            "command": "./server",
+            "stable period": "1m",
+            "backup strategy": [
+                { "times": 1, "period": "1h", "script": "cleanup.sh" },
+                { "times": 0, "period": "1h", "fallback": { "command": "./old-server" } },
+                { "times": 1, "period": "1h", "script": "alert.sh", "give up": true }
+            ]
+        }))
+        .unwrap();
+        let mut ladder = EscalationLadder::new(&command);
+        let quick = Duration::from_secs(1);
+
+        assert!(matches!(ladder.record_crash(), Escalation::Restart));
+        assert!(
+            matches!(ladder.record_crash(), Escalation::Stage(1, stage) if stage.script.is_some())
+        );
+        assert_eq!(ladder.command().command, "./server");
+        assert!(!ladder.ran_for(quick));
+
+        assert!(matches!(ladder.record_crash(), Escalation::Stage(2, _)));
+        assert_eq!(ladder.command().command, "./old-server");
+        assert_eq!(ladder.stage(), 2);
+
+        assert!(matches!(ladder.record_crash(), Escalation::Restart));
+        assert!(matches!(ladder.record_crash(), Escalation::Stage(3, stage) if stage.give_up));
+
+        // stable uptime goes back to the first stage and configured command
+        assert!(ladder.ran_for(Duration::from_secs(60)));
+        assert_eq!(ladder.command().command, "./server");
+        assert_eq!(ladder.stage(), 0);
+        assert!(!ladder.ran_for(Duration::from_secs(60)));
+        assert!(matches!(ladder.record_crash(), Escalation::Restart));
+        assert!(matches!(ladder.record_crash(), Escalation::Stage(1, _)));
+    }
+
+    #[test]
+    fn test_last_stage_repeats() {
+        let command = CommandConfig::parse_config(&json!({
+            "command": "./server",
+            "backup strategy": { "times": 0, "period": "1h", "script": "cleanup.sh" }
+        }))
+        .unwrap();
+        let mut ladder = EscalationLadder::new(&command);
+        assert!(matches!(ladder.record_crash(), Escalation::Stage(1, _)));
+        assert!(matches!(ladder.record_crash(), Escalation::Stage(1, _)));
+
+        let command = CommandConfig::parse_config(&json!({ "command": "./server" })).unwrap();
+        let mut ladder = EscalationLadder::new(&command);
+        assert!(matches!(ladder.record_crash(), Escalation::Restart));
+    }
+
+    #[test]
+    fn test_fallback_keeps_config() {
+        let command = CommandConfig::parse_config(&json!({
+            "command": "./server",
+            "args": ["--port", "8080"],
+            "mode": "keep alive",
+            "ready when": { "tcp port": 8080 },
+            "health checks": [ { "tcp port": 8080 } ],
+            "log": { "path": "server.log" },
+            "max memory": "1GB",
+            "nice": 5,
+            "backup strategy": {
+                "times": 0,
+                "period": "1h",
+                "fallback": { "command": "./old-server", "args": ["--legacy"] }
+            }
+        }))
+        .unwrap();
+        let mut ladder = EscalationLadder::new(&command);
+        assert!(matches!(ladder.record_crash(), Escalation::Stage(1, _)));
+
+        let fallback = ladder.command();
+        assert_eq!(fallback.command, "./old-server");
+        assert_eq!(fallback.args, vec![String::from("--legacy")]);
+        assert_eq!(fallback.name, command.name);
+        assert!(matches!(fallback.mode, CommandMode::KeepAlive));
+        assert_eq!(
+            fallback
+                .ready_when
+                .as_ref()
+                .and_then(|ready| ready.tcp_port),
+            Some(8080)
+        );
+        assert_eq!(fallback.health_checks.len(), 1);
+        assert_eq!(fallback.log, command.log);
+        assert_eq!(fallback.limits, command.limits);
+        assert_eq!(fallback.process, command.process);
+    }
+}
@@ -0,0 +1,98 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::io::{self, Write};
+
+use async_std::channel::Receiver;
+use chrono::Utc;
+
+use crate::{
+    runner_error::Result,
+    tui_state::{readiness_message, restart_message, TuiEvent},
+};
+
+// headless output thread. Replaces tui when there is no terminal, printing one line per event
+pub(crate) async fn run(rx: Receiver<TuiEvent>) -> Result<()> {
+    let mut names = Vec::new();
+    while let Ok(event) = rx.recv().await {
+        match event {
+            TuiEvent::TabListChanged(tabs) => {
+                names = tabs.into_iter().map(|(title, _)| title).collect()
+            }
+            TuiEvent::CommandStarted(idx, _, _) => {
+                print_line(&names, idx, "sys", "Command Started")?
+            }
+            TuiEvent::NewStdoutMessage(idx, message) => print_line(&names, idx, "out", &message)?,
+            TuiEvent::NewStderrMessage(idx, message) => print_line(&names, idx, "err", &message)?,
+            TuiEvent::CommandEnded(idx, _, _) => print_line(&names, idx, "sys", "Command ended")?,
+            TuiEvent::NewSystemMessage(idx, message) => print_line(&names, idx, "sys", &message)?,
+            TuiEvent::ReadinessChanged(idx, ready) => {
+                print_line(&names, idx, "sys", &readiness_message(ready))?
+            }
+            TuiEvent::RestartScheduled(idx, delay) => {
+                print_line(&names, idx, "sys", &restart_message(delay))?
+            }
+            TuiEvent::StateChanged(_, _)
+            | TuiEvent::ResourcesSampled(_, _)
+            | TuiEvent::Input(_) => {}
+            TuiEvent::Exit => break,
+        }
+    }
+    Ok(())
+}
+
+// prints single line with timestamp, command name and stream
+fn print_line(names: &[String], idx: usize, stream: &str, message: &str) -> Result<()> {
+    let line = format_line(names, idx, stream, message);
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(line.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+// formats line, padding names so messages of all commands are aligned
+fn format_line(names: &[String], idx: usize, stream: &str, message: &str) -> String {
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+    let name = names.get(idx).map(|name| name.as_str()).unwrap_or("?");
+    format!(
+        "{} {:width$} {} | {}\n",
+        Utc::now().format("%Y-%m-%d %H:%M:%S"),
+        name,
+        stream,
+        message,
+        width = width
+    )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_format_line() {
+        let names = vec![String::from("db"), String::from("server")];
+        let line = format_line(&names, 0, "out", "ready");
+        assert!(line.ends_with(" db     out | ready\n"));
+
+        let line = format_line(&names, 1, "err", "failed");
+        assert!(line.ends_with(" server err | failed\n"));
+
+        let line = format_line(&names, 5, "sys", "unknown");
+        assert!(line.ends_with(" ?      sys | unknown\n"));
+    }
+}
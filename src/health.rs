@@ -0,0 +1,164 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{sync::Mutex, time::Duration};
+
+use async_std::task;
+use futures::future;
+
+use crate::{
+    command_config::{HealthCheck, HealthProbe},
+    lock::lock,
+    probe::{check_port, ProbeContext},
+    runner_error::Result,
+    tui_state::TuiEvent,
+};
+
+// runs all health checks of a ready process. Once any check fails too many times in a row the
+// process is killed, so it goes through the same crash handling as a real crash, and reason is
+// stored for the crash report
+pub(crate) async fn monitor_health(
+    probe: &ProbeContext<'_>,
+    failure: &Mutex<Option<String>>,
+) -> Result<()> {
+    future::try_join_all(
+        probe
+            .config
+            .health_checks
+            .iter()
+            .map(|check| run_check(probe, check, failure)),
+    )
+    .await?;
+    Ok(())
+}
+
+// repeats single check until process is killed for failing health checks
+async fn run_check(
+    probe: &ProbeContext<'_>,
+    check: &HealthCheck,
+    failure: &Mutex<Option<String>>,
+) -> Result<()> {
+    let interval = check.interval.to_std().unwrap_or(Duration::ZERO);
+    let timeout = check.timeout.to_std().unwrap_or(Duration::ZERO);
+    let mut failures = 0u32;
+    loop {
+        task::sleep(interval).await;
+        if lock(failure).is_some() {
+            return Ok(());
+        }
+        let healthy = match &check.probe {
+            HealthProbe::Command(command) => probe.check_command(command, timeout).await,
+            HealthProbe::TcpPort(port) => check_port(*port, timeout).await,
+            HealthProbe::StdoutSilence(silence) => {
+                probe.stdout.silent_for() <= silence.to_std().unwrap_or(Duration::ZERO)
+            }
+        };
+        if healthy {
+            failures = 0;
+            continue;
+        }
+        failures += 1;
+        if failures < check.failures {
+            continue;
+        }
+
+        let reason = format!(
+            "Health check {} failed {} times in a row",
+            describe(&check.probe),
+            failures
+        );
+        {
+            let mut failure = lock(failure);
+            // another check may have already killed the process
+            if failure.is_some() {
+                return Ok(());
+            }
+            *failure = Some(reason.clone());
+        }
+        probe
+            .tx
+            .send(TuiEvent::NewSystemMessage(
+                probe.id,
+                format!("{}, restarting", reason),
+            ))
+            .await?;
+        probe.control.shutdown().kill(probe.pid);
+        return Ok(());
+    }
+}
+
+// human readable description of a probe
+fn describe(probe: &HealthProbe) -> String {
+    match probe {
+        HealthProbe::Command(command) => format!("command '{}'", command.join(" ")),
+        HealthProbe::TcpPort(port) => format!("tcp port {}", port),
+        HealthProbe::StdoutSilence(silence) => {
+            format!("stdout silence {}s", silence.num_seconds())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use async_std::{channel, prelude::FutureExt};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        command_config::CommandConfig, control::Control, probe::StdoutWatch, test_dir::TestDir,
+    };
+
+    #[test]
+    fn test_cancelled_check() {
+        let dir = TestDir::new("health");
+        let pid_file = dir.join("pid");
+        let line = format!("echo $$ > {}; exec sleep 10", pid_file.display());
+        let config = CommandConfig::parse_config(&json!({
+            "command": "./server",
+            "health checks": [
+                { "command": ["/bin/sh", "-c", line], "interval": "10ms", "timeout": "10s" }
+            ]
+        }))
+        .unwrap();
+        let control = Control::for_test(&["server"]);
+        let (tx, _rx) = channel::bounded(1);
+        let stdout = StdoutWatch::new(None);
+        let probe = ProbeContext {
+            config: &config,
+            env: &[],
+            stdout: &stdout,
+            tx: &tx,
+            id: 0,
+            pid: 0,
+            control: &control,
+        };
+        let failure = Mutex::new(None);
+
+        // process ending while its health check runs cancels the check, which kills the probe
+        let cancelled =
+            task::block_on(monitor_health(&probe, &failure).timeout(Duration::from_millis(500)));
+        assert!(cancelled.is_err());
+        assert!(lock(&failure).is_none());
+        let pid: libc::pid_t = std::fs::read_to_string(pid_file)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+    }
+}
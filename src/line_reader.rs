@@ -0,0 +1,189 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use async_std::io::{self, BufReader, Read};
+use futures::AsyncBufReadExt;
+
+// longest line passed on, in bytes. Rest of longer lines is dropped
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+// appended to lines that were cut at max line length
+const TRUNCATED_MARKER: &str = " [truncated]";
+
+// splits output of a process into lines. Unlike lines of BufReader it never fails on invalid
+// utf-8, which is replaced, keeps memory bounded on very long lines and passes on final line
+// without newline
+pub(crate) struct LineReader<R> {
+    reader: BufReader<R>,
+    max_length: usize,
+
+    // line read so far. Kept here, so reading can be cancelled without losing data
+    line: Vec<u8>,
+    truncated: bool,
+}
+
+impl<R: Read + Unpin> LineReader<R> {
+    // creates reader with default max line length
+    pub(crate) fn new(reader: R) -> LineReader<R> {
+        LineReader::with_max_length(reader, MAX_LINE_LENGTH)
+    }
+
+    // creates reader cutting lines at given length
+    pub(crate) fn with_max_length(reader: R, max_length: usize) -> LineReader<R> {
+        LineReader {
+            reader: BufReader::new(reader),
+            max_length,
+            line: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    // reads next line without line ending. Returns None once output is closed. Cancelling the
+    // returned future is safe, reading continues where it stopped on next call
+    pub(crate) async fn next_line(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                let partial = !self.line.is_empty() || self.truncated;
+                return Ok(partial.then(|| self.take_line()));
+            }
+            let (chunk, used, complete) = match available.iter().position(|&b| b == b'\n') {
+                Some(end) => (&available[..end], end + 1, true),
+                None => (available, available.len(), false),
+            };
+            let room = self.max_length.saturating_sub(self.line.len());
+            if chunk.len() > room {
+                self.truncated = true;
+            }
+            self.line.extend_from_slice(&chunk[..chunk.len().min(room)]);
+            self.reader.consume_unpin(used);
+            if complete {
+                return Ok(Some(self.take_line()));
+            }
+        }
+    }
+
+    // decodes line read so far and starts a new one
+    fn take_line(&mut self) -> String {
+        let truncated = std::mem::take(&mut self.truncated);
+        decode(std::mem::take(&mut self.line), truncated)
+    }
+}
+
+// decodes line, replacing invalid utf-8 and dropping carriage return of windows line ending
+fn decode(mut line: Vec<u8>, truncated: bool) -> String {
+    if !truncated && line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    let mut line = String::from_utf8_lossy(&line).into_owned();
+    if truncated {
+        line.push_str(TRUNCATED_MARKER);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+
+    use async_std::{channel, fs::File, task};
+
+    use super::*;
+    use crate::{
+        monitor_stderr::monitor_stderr,
+        monitor_stdout::{monitor_stdout, LogT},
+        probe::StdoutWatch,
+        test_dir::TestDir,
+        tui_state::TuiEvent,
+    };
+
+    fn read_all(input: &[u8], max_length: usize) -> Vec<String> {
+        task::block_on(async {
+            let mut reader = LineReader::with_max_length(input, max_length);
+            let mut lines = Vec::new();
+            while let Some(line) = reader.next_line().await.unwrap() {
+                lines.push(line);
+            }
+            lines
+        })
+    }
+
+    #[test]
+    fn test_next_line() {
+        assert_eq!(
+            read_all(b"first\nsecond\r\n\nlast", 100),
+            vec!["first", "second", "", "last"]
+        );
+        assert_eq!(read_all(b"bad \xff byte\n", 100), vec!["bad \u{fffd} byte"]);
+        assert_eq!(
+            read_all(b"0123456789\nshort\n0123456789", 4),
+            vec!["0123 [truncated]", "shor [truncated]", "0123 [truncated]"]
+        );
+        assert!(read_all(b"", 100).is_empty());
+    }
+
+    #[test]
+    fn test_binary_output() {
+        // same output examples/binary_output writes
+        let mut output: Vec<u8> = Vec::new();
+        output.extend_from_slice(b"plain line\n");
+        output.extend_from_slice(b"invalid \xff\xfe bytes\n");
+        output.extend_from_slice(b"windows line\r\n");
+        output.resize(output.len() + 100_000, b'x');
+        output.push(b'\n');
+        output.extend_from_slice(b"partial line");
+        let root = TestDir::new("binary-output");
+        let input = root.join("output.bin");
+        std::fs::write(&input, output).unwrap();
+
+        let (tx, rx) = channel::unbounded();
+        task::block_on(async {
+            let mut buffer = LogT::with_capacity(10);
+            let watch = StdoutWatch::new(None);
+            let stdout = File::open(&input).await.unwrap();
+            monitor_stdout(&mut buffer, stdout, tx.clone(), 0, &watch)
+                .await
+                .unwrap();
+            let stderr = File::open(&input).await.unwrap();
+            let err_path = root.join("command").to_string_lossy().into_owned();
+            monitor_stderr(err_path, stderr, tx, 0).await.unwrap();
+        });
+        let events: Vec<TuiEvent> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        let stdout: Vec<&str> = events
+            .iter()
+            .filter_map(|event| match event {
+                TuiEvent::NewStdoutMessage(0, line) => Some(line.as_str()),
+                _ => None,
+            })
+            .collect();
+        let stderr: Vec<&str> = events
+            .iter()
+            .filter_map(|event| match event {
+                TuiEvent::NewStderrMessage(0, line) => Some(line.as_str()),
+                _ => None,
+            })
+            .collect();
+        for lines in [stdout, stderr] {
+            assert_eq!(lines.len(), 5);
+            assert_eq!(lines[0], "plain line");
+            assert_eq!(lines[1], "invalid \u{fffd}\u{fffd} bytes");
+            assert_eq!(lines[2], "windows line");
+            assert_eq!(lines[3].len(), MAX_LINE_LENGTH + TRUNCATED_MARKER.len());
+            assert!(lines[3].ends_with(TRUNCATED_MARKER));
+            assert_eq!(lines[4], "partial line");
+        }
+    }
+}
@@ -15,43 +15,86 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <http://www.gnu.org/licenses/>.
 */
 
+mod backoff;
 mod command_config;
+mod command_log;
 mod config;
 mod config_error;
+mod control;
+mod control_client;
+mod control_server;
+mod crash_store;
+mod crash_window;
+mod escalation;
+mod headless;
+mod health;
+mod line_reader;
+mod lock;
 mod monitor_stderr;
 mod monitor_stdout;
+mod probe;
+mod readiness;
+mod resources;
 mod run_command;
 mod runner;
 mod runner_error;
+mod shutdown;
+#[cfg(test)]
+mod test_dir;
 mod tui;
 mod tui_state;
+mod ui_sender;
 
 use async_std::task;
-use clap::{crate_version, App, Arg};
+use clap::{crate_version, App, AppSettings, Arg, ArgMatches};
+use serde_json::json;
 
 use runner_error::{Result, RunnerError};
 
+// action requested on command line
+enum Action {
+    // run commands from config file, optionally without tui
+    Run(String, bool),
+
+    // send request to control socket of a running runner
+    Control(String, serde_json::Value),
+}
+
 // main function
 fn main() {
-    let config = match parse_args() {
-        Ok(config) => config,
+    let action = match parse_args() {
+        Ok(action) => action,
         Err(err) => {
             eprintln!("{}", err);
             return;
         }
     };
-    if let Err(err) = task::block_on(runner::run(config)) {
-        eprintln!("{}", err);
+    match action {
+        Action::Run(config, headless) => {
+            if let Err(err) = task::block_on(runner::run(config, headless)) {
+                eprintln!("{}", err);
+            }
+        }
+        Action::Control(socket, request) => {
+            if let Err(err) = control_client::run(&socket, request) {
+                eprintln!("{}", err);
+                // scripts using ctl rely on exit status to detect failed requests
+                std::process::exit(1);
+            }
+        }
     }
 }
 
 // parse arguments using clap
-// runner takes one mandatory argument, path to a config file
-fn parse_args() -> Result<String> {
-    App::new("Runner")
+// runner takes one mandatory argument, path to a config file, and optional headless flag.
+// ctl subcommand controls already running runner instead
+fn parse_args() -> Result<Action> {
+    let name = || Arg::new("name").help("Command name").required(true);
+    let matches = App::new("Runner")
         .version(crate_version!())
         .author("Jurij Robba <jurij.robba@lxnavigation.com>")
         .about("Runner and monitoring application")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::new("config")
                 .short('c')
@@ -61,8 +104,80 @@ fn parse_args() -> Result<String> {
                 .takes_value(true)
                 .required(true),
         )
-        .get_matches()
+        .arg(
+            Arg::new("headless")
+                .long("headless")
+                .help("Prints output as plain lines instead of starting interactive interface"),
+        )
+        .subcommand(
+            App::new("ctl")
+                .about("Controls running runner through its control socket")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .arg(
+                    Arg::new("socket")
+                        .short('s')
+                        .long("socket")
+                        .value_name("SOCKET")
+                        .help("Control socket of running runner")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .subcommand(App::new("list").about("Lists commands with their state and PID"))
+                .subcommand(
+                    App::new("start")
+                        .about("Starts stopped command")
+                        .arg(name()),
+                )
+                .subcommand(App::new("stop").about("Stops running command").arg(name()))
+                .subcommand(App::new("restart").about("Restarts command").arg(name()))
+                .subcommand(
+                    App::new("tail")
+                        .about("Shows recent output of command")
+                        .arg(name())
+                        .arg(
+                            Arg::new("lines")
+                                .short('n')
+                                .long("lines")
+                                .value_name("LINES")
+                                .help("Number of lines to show")
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .get_matches();
+
+    if let Some(ctl) = matches.subcommand_matches("ctl") {
+        return parse_control_args(ctl);
+    }
+    let config = matches
         .value_of("config")
-        .ok_or(RunnerError::MissingConfiguration)
-        .map(|val| val.to_owned())
+        .ok_or(RunnerError::MissingConfiguration)?
+        .to_owned();
+    Ok(Action::Run(config, matches.is_present("headless")))
+}
+
+// creates control request from ctl subcommand arguments
+fn parse_control_args(matches: &ArgMatches) -> Result<Action> {
+    let socket = matches
+        .value_of("socket")
+        .ok_or_else(|| RunnerError::ControlError(String::from("Missing control socket")))?
+        .to_owned();
+    let request = match matches.subcommand() {
+        Some(("list", _)) => json!({ "command": "list" }),
+        Some(("tail", args)) => {
+            let lines = args
+                .value_of("lines")
+                .map(|lines| lines.parse::<u64>())
+                .transpose()
+                .map_err(|_| RunnerError::ControlError(String::from("Bad number of lines")))?;
+            json!({ "command": "tail", "name": args.value_of("name"), "lines": lines })
+        }
+        Some((command, args)) => json!({ "command": command, "name": args.value_of("name") }),
+        None => {
+            return Err(RunnerError::ControlError(String::from(
+                "Missing ctl command",
+            )))
+        }
+    };
+    Ok(Action::Control(socket, request))
 }
@@ -15,42 +15,204 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <http://www.gnu.org/licenses/>.
 */
 
+use std::time::{Duration, Instant};
+
 use async_std::{
     channel::Sender,
     fs::{File, OpenOptions},
-    io::BufReader,
+    io::BufWriter,
     prelude::*,
 };
 use chrono::Utc;
 
-use crate::{runner_error::Result, tui_state::TuiEvent};
+use crate::{line_reader::LineReader, runner_error::Result, tui_state::TuiEvent};
+
+// longest time stderr lines stay buffered before they are written to stderr.txt
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// stderr.txt of a single process run. File and its folder are only created once process writes
+// to stderr, and are kept open until process exits
+struct StderrFile {
+    err_path: String,
+    file: Option<BufWriter<File>>,
+    dirty: bool,
+
+    // set once file could not be written, no more lines are saved after that
+    failed: bool,
+}
+
+impl StderrFile {
+    // creates writer for stderr.txt in given folder, nothing is created yet
+    fn new(err_path: String) -> StderrFile {
+        StderrFile {
+            err_path,
+            file: None,
+            dirty: false,
+            failed: false,
+        }
+    }
+
+    // appends line to buffer, opening the file on first line
+    async fn write(&mut self, err_string: &str) -> Result<()> {
+        if self.failed {
+            return Ok(());
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                std::fs::create_dir_all(&self.err_path)?;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(format!("{}/stderr.txt", self.err_path))
+                    .await?;
+                self.file.insert(BufWriter::new(file))
+            }
+        };
+        file.write_all(format!("{} | {}\n", Utc::now().format("%H:%M:%S"), err_string).as_bytes())
+            .await?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    // writes buffered lines to the file
+    async fn flush(&mut self) -> Result<()> {
+        if let (Some(file), true) = (&mut self.file, self.dirty) {
+            file.flush().await?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    // reports write or flush error and stops saving lines, as stderr of the process has to be
+    // read regardless
+    async fn check(&mut self, result: Result<()>, tx: &Sender<TuiEvent>, id: usize) -> Result<()> {
+        if let Err(err) = result {
+            self.file = None;
+            self.failed = true;
+            tx.send(TuiEvent::NewSystemMessage(
+                id,
+                format!("Cannot write stderr.txt, saving stderr stopped - {}", err),
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+}
 
-// runs another thread to monitor standard err. all outputs are stored in stderr.txt file in folder
+// runs another thread to monitor standard err. all outputs are stored in stderr.txt file in folder.
+// Lines are buffered and written once stderr stays quiet for a moment, at least every flush
+// interval and when process exits
 pub(crate) async fn monitor_stderr(
     err_path: String,
     stderr: File,
     tx: Sender<TuiEvent>,
     id: usize,
 ) -> Result<()> {
-    let mut lines = BufReader::new(stderr).lines();
-    while let Some(line) = lines.next().await {
-        let line = line?;
-        append_to_file(err_path.clone(), line.clone()).await?;
-        tx.try_send(TuiEvent::NewStderrMessage(id, line))?;
+    let mut file = StderrFile::new(err_path);
+    let result = forward_lines(&mut file, stderr, &tx, id).await;
+    // whatever was read before an error is still written
+    let flushed = file.flush().await;
+    file.check(flushed, &tx, id).await?;
+    result
+}
+
+// reads lines until stderr is closed
+async fn forward_lines(
+    file: &mut StderrFile,
+    stderr: File,
+    tx: &Sender<TuiEvent>,
+    id: usize,
+) -> Result<()> {
+    let mut lines = LineReader::new(stderr);
+    let mut flushed = Instant::now();
+    loop {
+        let line = match lines.next_line().timeout(FLUSH_INTERVAL).await {
+            Ok(line) => match line? {
+                Some(line) => line,
+                None => return Ok(()),
+            },
+            // no line in a while, so burst is over
+            Err(_) => {
+                let result = file.flush().await;
+                file.check(result, tx, id).await?;
+                flushed = Instant::now();
+                continue;
+            }
+        };
+        let result = file.write(&line).await;
+        file.check(result, tx, id).await?;
+        if flushed.elapsed() >= FLUSH_INTERVAL {
+            let result = file.flush().await;
+            file.check(result, tx, id).await?;
+            flushed = Instant::now();
+        }
+        tx.send(TuiEvent::NewStderrMessage(id, line)).await?;
     }
-    Ok(())
 }
 
-// appends line to appropriate error file
-async fn append_to_file(err_path: String, err_string: String) -> Result<()> {
-    std::fs::create_dir_all(&err_path)?;
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(err_path + "/stderr.txt")
-        .await?;
-
-    file.write_all(format!("{} | {}\n", Utc::now().format("%H:%M:%S"), err_string).as_bytes())
-        .await?;
-    Ok(())
+#[cfg(test)]
+mod tests {
+
+    use std::io::Write;
+
+    use async_std::{channel, task};
+
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_monitor_stderr() {
+        let root = TestDir::new("stderr");
+        let input = root.join("input.txt");
+        let mut writer = std::fs::File::create(&input).unwrap();
+        for index in 0..10_000 {
+            writeln!(writer, "line {}", index).unwrap();
+        }
+        drop(writer);
+
+        let (tx, rx) = channel::unbounded();
+        let err_path = root.join("command").to_string_lossy().into_owned();
+        task::block_on(async {
+            let stderr = File::open(&input).await.unwrap();
+            monitor_stderr(err_path.clone(), stderr, tx, 0)
+                .await
+                .unwrap();
+        });
+        assert_eq!(rx.len(), 10_000);
+
+        let saved = std::fs::read_to_string(format!("{}/stderr.txt", err_path)).unwrap();
+        let lines: Vec<&str> = saved.lines().collect();
+        assert_eq!(lines.len(), 10_000);
+        assert!(lines[9_999].ends_with(" | line 9999"));
+    }
+
+    #[test]
+    fn test_unwritable_stderr_file() {
+        let root = TestDir::new("stderr-unwritable");
+        let input = root.join("input.txt");
+        std::fs::write(&input, "first\nsecond\n").unwrap();
+
+        // folder can not be created under a file
+        let (tx, rx) = channel::unbounded();
+        let err_path = input.join("command").to_string_lossy().into_owned();
+        task::block_on(async {
+            let stderr = File::open(&input).await.unwrap();
+            monitor_stderr(err_path, stderr, tx, 0).await.unwrap();
+        });
+        let events: Vec<TuiEvent> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        let errors = events
+            .iter()
+            .filter(|event| matches!(event, TuiEvent::NewSystemMessage(0, _)))
+            .count();
+        let lines: Vec<&str> = events
+            .iter()
+            .filter_map(|event| match event {
+                TuiEvent::NewStderrMessage(0, line) => Some(line.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(errors, 1);
+        assert_eq!(lines, vec!["first", "second"]);
+    }
 }
@@ -18,29 +18,32 @@ along with this program. If not, see <http://www.gnu.org/licenses/>.
 use async_std::{
     channel::Sender,
     fs::{File, OpenOptions},
-    io::BufReader,
     prelude::*,
 };
 use chrono::{DateTime, Utc};
 use circular_queue::CircularQueue;
 
-use crate::{runner_error::Result, tui_state::TuiEvent};
+use crate::{
+    line_reader::LineReader, probe::StdoutWatch, runner_error::Result, tui_state::TuiEvent,
+};
 
 // log type for stderr
 pub(crate) type LogT = CircularQueue<(DateTime<Utc>, String)>;
 
-// monitors std in parent thread. returns only when command exits
+// monitors std in parent thread. returns only when command exits. Lines are also passed to
+// readiness and health checks
 pub(crate) async fn monitor_stdout(
     buffer: &mut LogT,
     stdout: File,
     tx: Sender<TuiEvent>,
     id: usize,
+    watch: &StdoutWatch<'_>,
 ) -> Result<()> {
-    let mut lines = BufReader::new(stdout).lines();
-    while let Some(line) = lines.next().await {
-        let line = line?;
+    let mut lines = LineReader::new(stdout);
+    while let Some(line) = lines.next_line().await? {
+        watch.check(&line);
         buffer.push((Utc::now(), line.clone()));
-        tx.try_send(TuiEvent::NewStdoutMessage(id, line))?;
+        tx.send(TuiEvent::NewStdoutMessage(id, line)).await?;
     }
     Ok(())
 }
@@ -0,0 +1,207 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_std::{channel::Sender, net::TcpStream, prelude::FutureExt, task};
+use regex::Regex;
+
+use crate::{command_config::CommandConfig, control::Control, lock::lock, tui_state::TuiEvent};
+
+// how often running probe command is checked for exit
+const PROBE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// everything readiness and health checks need to know about running process
+pub(crate) struct ProbeContext<'a> {
+    pub(crate) config: &'a CommandConfig,
+    pub(crate) env: &'a [(OsString, OsString)],
+    pub(crate) stdout: &'a StdoutWatch<'a>,
+    pub(crate) tx: &'a Sender<TuiEvent>,
+    pub(crate) id: usize,
+    pub(crate) pid: u32,
+    pub(crate) control: &'a Control,
+}
+
+impl ProbeContext<'_> {
+    // resolves path relative to working directory of the command
+    pub(crate) fn resolve(&self, file: &str) -> PathBuf {
+        match &self.config.cwd {
+            Some(cwd) => Path::new(cwd).join(file),
+            None => Path::new(file).to_path_buf(),
+        }
+    }
+
+    // whether probe command exits successfully. Probe that does not finish in time is killed
+    pub(crate) async fn check_command(&self, command: &[String], timeout: Duration) -> bool {
+        let mut probe = Command::new(&command[0]);
+        probe
+            .args(&command[1..])
+            .env_clear()
+            .envs(self.env.iter().cloned())
+            // output of the probe is not interesting, only its exit status
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        if let Some(cwd) = &self.config.cwd {
+            probe.current_dir(cwd);
+        }
+        let mut probe = match probe.spawn() {
+            Ok(probe) => ProbeProcess(probe),
+            Err(_) => return false,
+        };
+        let deadline = Instant::now() + timeout;
+        loop {
+            match probe.0.try_wait() {
+                Ok(Some(status)) => return status.success(),
+                Ok(None) if Instant::now() < deadline => task::sleep(PROBE_POLL_INTERVAL).await,
+                // probe that did not finish in time is killed once dropped
+                _ => return false,
+            }
+        }
+    }
+}
+
+// running probe command. Probe still running once dropped is killed, so that probes of cancelled
+// checks do not outlive them
+struct ProbeProcess(Child);
+
+impl Drop for ProbeProcess {
+    fn drop(&mut self) {
+        if let Ok(None) = self.0.try_wait() {
+            let _ = self.0.kill();
+            // reaping killed probe does not block for long
+            let _ = self.0.wait();
+        }
+    }
+}
+
+// whether local port accepts connections
+pub(crate) async fn check_port(port: u16, timeout: Duration) -> bool {
+    matches!(
+        TcpStream::connect(("127.0.0.1", port))
+            .timeout(timeout)
+            .await,
+        Ok(Ok(_))
+    )
+}
+
+// follows stdout of a process for readiness log pattern and health checks
+pub(crate) struct StdoutWatch<'a> {
+    // readiness log pattern, if any
+    pattern: Option<&'a Regex>,
+
+    // whether any line matched the pattern
+    matched: AtomicBool,
+
+    // time of the last line, or process start
+    last_line: Mutex<Instant>,
+}
+
+impl<'a> StdoutWatch<'a> {
+    // creates watch for freshly started process
+    pub(crate) fn new(pattern: Option<&'a Regex>) -> StdoutWatch<'a> {
+        StdoutWatch {
+            pattern,
+            matched: AtomicBool::new(false),
+            last_line: Mutex::new(Instant::now()),
+        }
+    }
+
+    // checks single stdout line
+    pub(crate) fn check(&self, line: &str) {
+        *lock(&self.last_line) = Instant::now();
+        if let Some(pattern) = self.pattern {
+            if !self.matched() && pattern.is_match(line) {
+                self.matched.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // whether line matching the pattern was seen. Always true without pattern
+    pub(crate) fn matched(&self) -> bool {
+        self.pattern.is_none() || self.matched.load(Ordering::Relaxed)
+    }
+
+    // time since the last line
+    pub(crate) fn silent_for(&self) -> Duration {
+        lock(&self.last_line).elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use async_std::channel;
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    // whether process with given pid exists
+    fn is_alive(pid: &str) -> bool {
+        unsafe { libc::kill(pid.trim().parse().unwrap(), 0) == 0 }
+    }
+
+    #[test]
+    fn test_check_command() {
+        let config = CommandConfig::parse_config(&json!({ "command": "./server" })).unwrap();
+        let control = Control::for_test(&["server"]);
+        let (tx, _rx) = channel::bounded(1);
+        let stdout = StdoutWatch::new(None);
+        let probe = ProbeContext {
+            config: &config,
+            env: &[],
+            stdout: &stdout,
+            tx: &tx,
+            id: 0,
+            pid: 0,
+            control: &control,
+        };
+        let command = |line: &str| vec![String::from("/bin/sh"), String::from("-c"), line.into()];
+        let second = Duration::from_secs(1);
+
+        assert!(task::block_on(
+            probe.check_command(&command("true"), second)
+        ));
+        assert!(!task::block_on(
+            probe.check_command(&command("false"), second)
+        ));
+        assert!(!task::block_on(
+            probe.check_command(&command("sleep 10"), Duration::from_millis(50))
+        ));
+
+        // probe of a check cancelled while it runs is killed too
+        let dir = TestDir::new("probe");
+        let pid_file = dir.join("pid");
+        let sleep = command(&format!("echo $$ > {}; exec sleep 10", pid_file.display()));
+        let cancelled = task::block_on(
+            probe
+                .check_command(&sleep, Duration::from_secs(10))
+                .timeout(Duration::from_millis(500)),
+        );
+        assert!(cancelled.is_err());
+        assert!(!is_alive(&std::fs::read_to_string(pid_file).unwrap()));
+    }
+}
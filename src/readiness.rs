@@ -0,0 +1,97 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::time::Duration;
+
+use async_std::{prelude::FutureExt, task};
+
+use crate::{
+    command_config::{ReadyFailure, ReadyWhen},
+    probe::{check_port, ProbeContext},
+    runner_error::Result,
+    tui_state::TuiEvent,
+};
+
+// waits for started process to become ready and marks command as satisfied, or acts on failure
+// when it does not become ready in time. Returns whether process is considered ready
+pub(crate) async fn monitor_readiness(
+    probe: &ProbeContext<'_>,
+    ready_when: &ReadyWhen,
+) -> Result<bool> {
+    probe
+        .tx
+        .send(TuiEvent::ReadinessChanged(probe.id, false))
+        .await?;
+    let ready = wait_until_ready(probe, ready_when)
+        .timeout(ready_when.timeout.to_std().unwrap_or(Duration::ZERO))
+        .await
+        .is_ok();
+    if !ready {
+        probe
+            .tx
+            .send(TuiEvent::NewSystemMessage(
+                probe.id,
+                String::from("Command did not become ready in time"),
+            ))
+            .await?;
+        match ready_when.on_failure {
+            ReadyFailure::Restart => {
+                probe.control.shutdown().kill(probe.pid);
+                return Ok(false);
+            }
+            ReadyFailure::Stop => {
+                probe.control.stop(probe.id, probe.tx).await?;
+                return Ok(false);
+            }
+            ReadyFailure::Ignore => {}
+        }
+    }
+    probe.control.set_satisfied(probe.id);
+    probe
+        .tx
+        .send(TuiEvent::ReadinessChanged(probe.id, true))
+        .await?;
+    Ok(true)
+}
+
+// checks readiness in configured interval until all checks pass
+async fn wait_until_ready(probe: &ProbeContext<'_>, ready_when: &ReadyWhen) {
+    let interval = ready_when.interval.to_std().unwrap_or(Duration::ZERO);
+    loop {
+        if probe.stdout.matched()
+            && ready_when
+                .file
+                .as_ref()
+                .is_none_or(|file| probe.resolve(file).exists())
+            && match ready_when.tcp_port {
+                Some(port) => check_port(port, interval).await,
+                None => true,
+            }
+            && match &ready_when.command {
+                Some(command) => {
+                    probe
+                        .check_command(command, interval.max(Duration::from_secs(1)))
+                        .await
+                }
+                None => true,
+            }
+        {
+            return;
+        }
+        task::sleep(interval).await;
+    }
+}
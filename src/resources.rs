@@ -0,0 +1,384 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_std::task;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    command_config::ResourceLimits,
+    lock::lock,
+    probe::ProbeContext,
+    runner_error::Result,
+    tui_state::{format_memory, TuiEvent},
+};
+
+// how often resource usage of running processes is sampled
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+// number of most recent samples kept for crash reports
+const SAMPLE_HISTORY: usize = 60usize;
+
+// resource usage of a process together with all of its descendants at one point in time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ResourceUsage {
+    // when sample was taken
+    pub(crate) time: DateTime<Utc>,
+
+    // cpu time used since previous sample, in percent of a single core
+    pub(crate) cpu: f64,
+
+    // resident memory in bytes
+    pub(crate) memory: u64,
+
+    // number of threads
+    pub(crate) threads: u64,
+
+    // number of open file descriptors
+    pub(crate) open_files: u64,
+}
+
+// counters of a single process read from /proc/<pid>/stat
+#[derive(Debug, PartialEq)]
+struct ProcessStat {
+    // parent process id
+    ppid: u32,
+
+    // user and system cpu time in clock ticks
+    cpu_ticks: u64,
+
+    // number of threads
+    threads: u64,
+}
+
+// samples resource usage of a process tree, measuring cpu usage between samples
+pub(crate) struct ResourceSampler {
+    pid: u32,
+
+    // clock ticks per second cpu time is measured in
+    ticks_per_second: f64,
+
+    // cpu ticks of previous sample and when it was taken
+    previous: (u64, Instant),
+}
+
+impl ResourceSampler {
+    // creates sampler for a just started process
+    pub(crate) fn new(pid: u32) -> ResourceSampler {
+        // sysconf has no preconditions and returns -1 on error
+        let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        ResourceSampler {
+            pid,
+            ticks_per_second: if ticks_per_second > 0 {
+                ticks_per_second as f64
+            } else {
+                100.0
+            },
+            previous: (0, Instant::now()),
+        }
+    }
+
+    // samples process and its descendants. Returns None once process is gone
+    pub(crate) fn sample(&mut self) -> Option<ResourceUsage> {
+        let now = Instant::now();
+        let mut usage = ResourceUsage {
+            time: Utc::now(),
+            cpu: 0.0,
+            memory: 0,
+            threads: 0,
+            open_files: 0,
+        };
+        let mut cpu_ticks = 0u64;
+        for (pid, stat) in process_tree(self.pid)? {
+            cpu_ticks += stat.cpu_ticks;
+            usage.threads += stat.threads;
+            usage.memory += read_memory(pid).unwrap_or(0);
+            usage.open_files += count_open_files(pid).unwrap_or(0);
+        }
+        // descendants that exited since previous sample take their cpu time with them
+        let (previous_ticks, previous_time) = self.previous;
+        let elapsed = now.duration_since(previous_time).as_secs_f64();
+        if elapsed > 0.0 {
+            usage.cpu =
+                cpu_ticks.saturating_sub(previous_ticks) as f64 / self.ticks_per_second / elapsed
+                    * 100.0;
+        }
+        self.previous = (cpu_ticks, now);
+        Some(usage)
+    }
+}
+
+// checks resource usage samples against limits of a command
+struct Watchdog<'a> {
+    limits: &'a ResourceLimits,
+
+    // time of first sample of current streak over cpu limit
+    cpu_over_since: Option<DateTime<Utc>>,
+}
+
+impl Watchdog<'_> {
+    // returns description of violated limit, if sample violates any. Every sample counts towards
+    // cpu streak, even one over memory limit
+    fn check(&mut self, usage: &ResourceUsage) -> Option<String> {
+        let cpu = self.check_cpu(usage);
+        match self.limits.max_memory {
+            Some(max_memory) if usage.memory > max_memory => Some(format!(
+                "Memory {} over limit {}",
+                format_memory(usage.memory),
+                format_memory(max_memory)
+            )),
+            _ => cpu,
+        }
+    }
+
+    // updates streak over cpu limit, returning description once it lasts too long
+    fn check_cpu(&mut self, usage: &ResourceUsage) -> Option<String> {
+        let max_cpu = self.limits.max_cpu?;
+        if usage.cpu <= max_cpu {
+            self.cpu_over_since = None;
+            return None;
+        }
+        let since = *self.cpu_over_since.get_or_insert(usage.time);
+        if usage.time - since < self.limits.max_cpu_for {
+            return None;
+        }
+        Some(format!(
+            "CPU over limit {}% for {}s",
+            max_cpu,
+            (usage.time - since).num_seconds()
+        ))
+    }
+}
+
+// samples resource usage of running process until it exits, reporting every sample to interface
+// and keeping recent ones for the crash report. Process over resource limits of its command is
+// killed, so it goes through the same crash handling as a real crash, and violated limit is stored
+// for the crash report. Never returns on success
+pub(crate) async fn monitor_resources(
+    probe: &ProbeContext<'_>,
+    samples: &Mutex<VecDeque<ResourceUsage>>,
+    violation: &Mutex<Option<String>>,
+) -> Result<()> {
+    let mut sampler = ResourceSampler::new(probe.pid);
+    let mut watchdog = Watchdog {
+        limits: &probe.config.limits,
+        cpu_over_since: None,
+    };
+    let mut killed = false;
+    loop {
+        task::sleep(SAMPLE_INTERVAL).await;
+        // reading /proc blocks, so it is kept off async tasks
+        let (returned, usage) = task::spawn_blocking(move || {
+            let usage = sampler.sample();
+            (sampler, usage)
+        })
+        .await;
+        sampler = returned;
+        let usage = match usage {
+            Some(usage) => usage,
+            None => continue,
+        };
+        {
+            let mut samples = lock(samples);
+            samples.push_back(usage);
+            while samples.len() > SAMPLE_HISTORY {
+                samples.pop_front();
+            }
+        }
+        probe
+            .tx
+            .send(TuiEvent::ResourcesSampled(probe.id, usage))
+            .await?;
+        if killed {
+            continue;
+        }
+        if let Some(reason) = watchdog.check(&usage) {
+            *lock(violation) = Some(reason.clone());
+            probe
+                .tx
+                .send(TuiEvent::NewSystemMessage(
+                    probe.id,
+                    format!("{}, restarting", reason),
+                ))
+                .await?;
+            probe.control.shutdown().kill(probe.pid);
+            killed = true;
+        }
+    }
+}
+
+// finds process and all of its descendants with their counters. Returns None if process is gone
+fn process_tree(pid: u32) -> Option<Vec<(u32, ProcessStat)>> {
+    let mut children: HashMap<u32, Vec<(u32, ProcessStat)>> = HashMap::new();
+    let mut root = None;
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let other = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            Some(other) => other,
+            None => continue,
+        };
+        // processes may exit while /proc is read
+        let stat = match read_stat(other) {
+            Some(stat) => stat,
+            None => continue,
+        };
+        if other == pid {
+            root = Some(stat);
+        } else {
+            children.entry(stat.ppid).or_default().push((other, stat));
+        }
+    }
+    let mut tree = vec![(pid, root?)];
+    let mut index = 0;
+    while index < tree.len() {
+        tree.extend(children.remove(&tree[index].0).unwrap_or_default());
+        index += 1;
+    }
+    Some(tree)
+}
+
+// reads /proc/<pid>/stat
+fn read_stat(pid: u32) -> Option<ProcessStat> {
+    parse_stat(&std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?)
+}
+
+// parses content of /proc/<pid>/stat. Command name in parentheses may contain spaces, so fields
+// are counted from its closing parenthesis, starting with the third field
+fn parse_stat(content: &str) -> Option<ProcessStat> {
+    let fields: Vec<&str> = content
+        .get(content.rfind(')')? + 1..)?
+        .split_whitespace()
+        .collect();
+    let field = |number: usize| -> Option<u64> { fields.get(number - 3)?.parse().ok() };
+    Some(ProcessStat {
+        ppid: u32::try_from(field(4)?).ok()?,
+        cpu_ticks: field(14)? + field(15)?,
+        threads: field(20)?,
+    })
+}
+
+// resident memory of a process in bytes, from VmRSS of /proc/<pid>/status
+fn read_memory(pid: u32) -> Option<u64> {
+    parse_memory(&std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?)
+}
+
+// parses VmRSS line of /proc/<pid>/status, given in KB. Kernel threads have none
+fn parse_memory(content: &str) -> Option<u64> {
+    let line = content.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+// number of open file descriptors of a process
+fn count_open_files(pid: u32) -> Option<u64> {
+    Some(std::fs::read_dir(format!("/proc/{}/fd", pid)).ok()?.count() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_proc() {
+        let stat = "1234 (my (odd) server) S 1 1234 1234 0 -1 4194560 1000 0 0 0 150 50 0 0 20 0 \
+                    7 0 100 10000000 500 18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 0 0 0";
+        assert_eq!(
+            parse_stat(stat),
+            Some(ProcessStat {
+                ppid: 1,
+                cpu_ticks: 200,
+                threads: 7,
+            })
+        );
+        assert_eq!(parse_stat("1234 (server"), None);
+
+        let status = "Name:\tserver\nVmPeak:\t  20000 kB\nVmRSS:\t   1500 kB\nThreads:\t7\n";
+        assert_eq!(parse_memory(status), Some(1500 * 1024));
+        assert_eq!(parse_memory("Name:\tkthreadd\n"), None);
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let limits = ResourceLimits {
+            max_memory: Some(1 << 20),
+            max_cpu: Some(50f64),
+            max_cpu_for: chrono::Duration::seconds(2),
+        };
+        let mut watchdog = Watchdog {
+            limits: &limits,
+            cpu_over_since: None,
+        };
+        let start = Utc::now();
+        let usage = |seconds: i64, cpu: f64, memory: u64| ResourceUsage {
+            time: start + chrono::Duration::seconds(seconds),
+            cpu,
+            memory,
+            threads: 1,
+            open_files: 3,
+        };
+        assert_eq!(watchdog.check(&usage(0, 10.0, 1024)), None);
+        assert_eq!(
+            watchdog.check(&usage(1, 10.0, 2 << 20)),
+            Some(String::from("Memory 2.0 MB over limit 1.0 MB"))
+        );
+
+        // cpu has to stay over its limit for entire window
+        assert_eq!(watchdog.check(&usage(2, 80.0, 1024)), None);
+        assert_eq!(watchdog.check(&usage(3, 80.0, 1024)), None);
+        assert_eq!(watchdog.check(&usage(4, 40.0, 1024)), None);
+        assert_eq!(watchdog.check(&usage(5, 80.0, 1024)), None);
+        assert_eq!(watchdog.check(&usage(6, 80.0, 1024)), None);
+        assert_eq!(
+            watchdog.check(&usage(7, 80.0, 1024)),
+            Some(String::from("CPU over limit 50% for 2s"))
+        );
+
+        // sample over memory limit still counts towards cpu streak
+        assert_eq!(watchdog.check(&usage(8, 10.0, 1024)), None);
+        assert_eq!(
+            watchdog.check(&usage(9, 80.0, 2 << 20)),
+            Some(String::from("Memory 2.0 MB over limit 1.0 MB"))
+        );
+        assert_eq!(watchdog.check(&usage(10, 80.0, 1024)), None);
+        assert_eq!(
+            watchdog.check(&usage(11, 80.0, 1024)),
+            Some(String::from("CPU over limit 50% for 2s"))
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        // runner itself has at least one thread, some memory and open files
+        let mut sampler = ResourceSampler::new(std::process::id());
+        let usage = sampler.sample().unwrap();
+        assert!(usage.threads >= 1);
+        assert!(usage.memory > 0);
+        assert!(usage.open_files > 0);
+        assert!(usage.cpu >= 0.0);
+
+        assert!(ResourceSampler::new(u32::MAX).sample().is_none());
+    }
+}
@@ -15,84 +15,389 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <http://www.gnu.org/licenses/>.
 */
 
-use async_std::{channel::Sender, task};
+use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
+    ffi::OsString,
+    hash::{Hash, Hasher},
+    io,
+    os::{
+        fd::OwnedFd,
+        unix::process::{CommandExt, ExitStatusExt},
+    },
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::Mutex,
+    time::Duration,
+};
+
+use async_std::{channel::Sender, fs::OpenOptions, future, prelude::*, task};
 use chrono::{DateTime, Utc};
-use subprocess::{ExitStatus, Popen, PopenConfig, Redirection};
 
 use crate::{
-    command_config::CommandConfig,
+    command_config::{CommandConfig, IoClass, ProcessSettings, Rlimit},
+    control::Control,
+    lock::into_inner,
     monitor_stdout::LogT,
+    probe::{ProbeContext, StdoutWatch},
+    resources::ResourceUsage,
     runner_error::{Result, RunnerError},
     tui_state::TuiEvent,
 };
 
+// ioprio_set target selecting a single process
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+// position of io scheduling class within io priority
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+// position of a run within supervision of its command, recorded in crash reports and shown in
+// the interface
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct RunInfo {
+    // number of restarts before this run, 0 for first run
+    pub(crate) restart: u64,
+
+    // backup strategy stage reached before this run, 0 if command was not escalated
+    pub(crate) stage: usize,
+
+    // crashes counted towards crash limit of current backup strategy stage
+    pub(crate) recent_crashes: usize,
+
+    // whether run is a backup strategy script or safe mode action rather than supervised process
+    pub(crate) action: bool,
+}
+
+// everything crash.json describes about a crashed process
+struct CrashReport<'a> {
+    config: &'a CommandConfig,
+    env: &'a [(OsString, OsString)],
+    exit_status: ExitStatus,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    pid: u32,
+    run: RunInfo,
+    health_failure: Option<&'a str>,
+    limit_violation: Option<&'a str>,
+    resources: &'a [ResourceUsage],
+}
+
 // runs command, starting stdout and stderr monitoring
 pub(crate) async fn run_command(
     config: &CommandConfig,
     error_path: String,
     tx: Sender<TuiEvent>,
     id: usize,
+    control: &Control,
+    run_info: RunInfo,
 ) -> Result<()> {
-    tx.try_send(TuiEvent::CommandStarted(id))?;
-    let (mut process, start) = run(&config.command, &config.args)?;
+    if control.should_stop(id) {
+        return Ok(());
+    }
+    let env = create_environment(config);
+    let (mut process, start) = run(config, env.clone())?;
+    let pid = process.id();
+    tx.send(TuiEvent::CommandStarted(id, pid, run_info)).await?;
+    control.shutdown().register(
+        id,
+        pid,
+        config.stop_signal,
+        config.stop_timeout.to_std().unwrap_or(Duration::ZERO),
+    );
+    if config.satisfied_when_started() {
+        control.set_satisfied(id);
+    }
     let process_folder = format!(
         "{}/{}-{}",
         error_path,
         config.name,
         start.format("%Y-%m-%d_%H:%M:%S")
     );
+    let live_folder = control.crash_store().live(&process_folder);
 
     let stderr_handle = task::spawn(crate::monitor_stderr::monitor_stderr(
         process_folder.clone(),
-        process
-            .stderr
-            .take()
-            .ok_or(RunnerError::CannotGetStderr)?
-            .into(),
+        std::fs::File::from(OwnedFd::from(
+            process.stderr.take().ok_or(RunnerError::CannotGetStderr)?,
+        ))
+        .into(),
         tx.clone(),
         id,
     ));
 
     let mut buffer = LogT::with_capacity(config.stdout_history);
-    crate::monitor_stdout::monitor_stdout(
+    let stdout_watch = StdoutWatch::new(
+        config
+            .ready_when
+            .as_ref()
+            .and_then(|ready_when| ready_when.log_pattern.as_ref()),
+    );
+    let stdout = crate::monitor_stdout::monitor_stdout(
         &mut buffer,
-        process
-            .stdout
-            .take()
-            .ok_or(RunnerError::CannotGetStdout)?
-            .into(),
+        std::fs::File::from(OwnedFd::from(
+            process.stdout.take().ok_or(RunnerError::CannotGetStdout)?,
+        ))
+        .into(),
         tx.clone(),
         id,
-    )
-    .await?;
+        &stdout_watch,
+    );
+    let health_failure = Mutex::new(None);
+    let samples = Mutex::new(VecDeque::new());
+    let limit_violation = Mutex::new(None);
+    let probe = ProbeContext {
+        config,
+        env: &env,
+        stdout: &stdout_watch,
+        tx: &tx,
+        id,
+        pid,
+        control,
+    };
+    // probes and resource sampling never finish on their own, so this ends together with stdout
+    stdout
+        .race(monitor_probes(&probe, &health_failure))
+        .race(crate::resources::monitor_resources(
+            &probe,
+            &samples,
+            &limit_violation,
+        ))
+        .await?;
 
     stderr_handle.await?;
 
-    let exit_status = process.wait()?;
-    tx.try_send(TuiEvent::CommandEnded(id))?;
-    if exit_status != ExitStatus::Exited(0u32) {
-        crate::monitor_stdout::save_to_file(buffer, process_folder).await?;
+    let exit_status = process.wait();
+    let end = Utc::now();
+    control.shutdown().unregister(pid);
+    let exit_status = exit_status.map_err(RunnerError::ProcessError)?;
+    // process stopped by runner or through control socket did not crash
+    let crashed = !control.should_stop(id) && !exit_status.success();
+    tx.send(TuiEvent::CommandEnded(id, exit_status, crashed))
+        .await?;
+    if crashed {
+        crate::monitor_stdout::save_to_file(buffer, process_folder.clone()).await?;
+        save_environment(&env, process_folder.clone()).await?;
+        let health_failure = into_inner(health_failure);
+        if let Some(reason) = &health_failure {
+            save_health_failure(reason, process_folder.clone()).await?;
+        }
+        let limit_violation = into_inner(limit_violation);
+        let mut samples = into_inner(samples);
+        let report = CrashReport {
+            config,
+            env: &env,
+            exit_status,
+            start,
+            end,
+            pid,
+            run: run_info,
+            health_failure: health_failure.as_deref(),
+            limit_violation: limit_violation.as_deref(),
+            resources: samples.make_contiguous(),
+        };
+        save_crash_report(&report, process_folder.clone()).await?;
+        save_config(config, process_folder.clone()).await?;
+        control.crash_store().archive(&process_folder).await?;
+    }
+    // new crash folder or archive, or folder with stderr only, may push crash path over its limits
+    drop(live_folder);
+    if crashed || Path::new(&process_folder).exists() {
+        control.crash_store().clean().await;
+    }
+    if crashed {
         return Err(RunnerError::ExitError(exit_status));
     }
     Ok(())
 }
 
-// run detached with stdout and stderr piped
-fn run(command: &str, args: &[String]) -> Result<(Popen, DateTime<Utc>)> {
+// waits for process to become ready and then keeps checking its health. Never returns on success
+async fn monitor_probes(
+    probe: &ProbeContext<'_>,
+    health_failure: &Mutex<Option<String>>,
+) -> Result<()> {
+    let ready = match &probe.config.ready_when {
+        Some(ready_when) => crate::readiness::monitor_readiness(probe, ready_when).await?,
+        None => true,
+    };
+    if ready {
+        crate::health::monitor_health(probe, health_failure).await?;
+    }
+    future::pending().await
+}
+
+// run in its own process group with stdout and stderr piped. Process settings are applied in the
+// started process before it executes the command
+fn run(config: &CommandConfig, env: Vec<(OsString, OsString)>) -> Result<(Child, DateTime<Utc>)> {
+    let command = create_command(&config.command, &config.args);
+    let mut process = Command::new(command[0]);
+    process
+        .args(&command[1..])
+        .env_clear()
+        .envs(env)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0);
+    if let Some(cwd) = &config.cwd {
+        process.current_dir(cwd);
+    }
+    let settings = config.process.clone();
+    // settings are applied with async signal safe calls only, as required between fork and exec
+    unsafe {
+        process.pre_exec(move || apply_settings(&settings));
+    }
     Ok((
-        Popen::create(
-            &create_command(command, args),
-            PopenConfig {
-                stdout: Redirection::Pipe,
-                stderr: Redirection::Pipe,
-                detached: true,
-                ..Default::default()
-            },
-        )?,
+        process.spawn().map_err(RunnerError::ProcessError)?,
         Utc::now(),
     ))
 }
 
+// applies rlimits, priorities and umask to current process
+fn apply_settings(settings: &ProcessSettings) -> io::Result<()> {
+    let check = |result: libc::c_long| {
+        if result == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+    for &(rlimit, limit) in &settings.rlimits {
+        let resource = match rlimit {
+            Rlimit::OpenFiles => libc::RLIMIT_NOFILE,
+            Rlimit::Core => libc::RLIMIT_CORE,
+            Rlimit::AddressSpace => libc::RLIMIT_AS,
+            Rlimit::Cpu => libc::RLIMIT_CPU,
+        };
+        let limit = libc::rlimit {
+            rlim_cur: limit,
+            rlim_max: limit,
+        };
+        check(unsafe { libc::setrlimit(resource, &limit) }.into())?;
+    }
+    if let Some(nice) = settings.nice {
+        check(unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) }.into())?;
+    }
+    if let Some((class, level)) = settings.ionice {
+        let class = match class {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        };
+        // ioprio_set has no libc wrapper
+        let priority = (class << IOPRIO_CLASS_SHIFT) | libc::c_int::from(level);
+        check(unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority) })?;
+    }
+    if let Some(umask) = settings.umask {
+        unsafe {
+            libc::umask(umask as libc::mode_t);
+        }
+    }
+    Ok(())
+}
+
+// creates effective environment of the process from runner's environment and configured variables
+fn create_environment(config: &CommandConfig) -> Vec<(OsString, OsString)> {
+    let mut env: Vec<(OsString, OsString)> = if config.clear_env {
+        Vec::new()
+    } else {
+        std::env::vars_os().collect()
+    };
+    for (key, value) in &config.env {
+        env.retain(|(existing, _)| existing != key.as_str());
+        env.push((key.into(), value.into()));
+    }
+    env
+}
+
+// saves effective environment of crashed process to environment.txt
+async fn save_environment(env: &[(OsString, OsString)], err_path: String) -> Result<()> {
+    std::fs::create_dir_all(&err_path)?;
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(err_path + "/environment.txt")
+        .await?;
+
+    for (key, value) in env {
+        file.write_all(
+            format!("{}={}\n", key.to_string_lossy(), value.to_string_lossy()).as_bytes(),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+// saves crash.json manifest describing crashed process
+async fn save_crash_report(report: &CrashReport<'_>, err_path: String) -> Result<()> {
+    std::fs::create_dir_all(&err_path)?;
+    async_std::fs::write(
+        err_path + "/crash.json",
+        format!("{:#}\n", crash_manifest(report)),
+    )
+    .await?;
+    Ok(())
+}
+
+// creates crash.json content
+fn crash_manifest(report: &CrashReport<'_>) -> serde_json::Value {
+    let (exit_code, signal) = (report.exit_status.code(), report.exit_status.signal());
+    serde_json::json!({
+        "name": report.config.name,
+        "command": report.config.command,
+        "args": report.config.args,
+        "cwd": report.config.cwd,
+        "env hash": format!("{:016x}", hash_environment(report.env)),
+        "exit code": exit_code,
+        "signal": signal,
+        "start": report.start.to_rfc3339(),
+        "end": report.end.to_rfc3339(),
+        "uptime ms": (report.end - report.start).num_milliseconds(),
+        "pid": report.pid,
+        "restart": report.run.restart,
+        "backup strategy stage": report.run.stage,
+        "health check": report.health_failure,
+        "resource limit": report.limit_violation,
+        "resources": report
+            .resources
+            .iter()
+            .map(|usage| {
+                serde_json::json!({
+                    "time": usage.time.to_rfc3339(),
+                    "cpu percent": usage.cpu,
+                    "memory bytes": usage.memory,
+                    "threads": usage.threads,
+                    "open files": usage.open_files,
+                })
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+// hash of effective environment, independent of variable order. Equal environments have equal
+// hashes, so crashes caused by environment changes are easy to spot
+fn hash_environment(env: &[(OsString, OsString)]) -> u64 {
+    let mut env = env.to_vec();
+    env.sort();
+    let mut hasher = DefaultHasher::new();
+    env.hash(&mut hasher);
+    hasher.finish()
+}
+
+// saves snapshot of effective command config to config.txt
+async fn save_config(config: &CommandConfig, err_path: String) -> Result<()> {
+    std::fs::create_dir_all(&err_path)?;
+    async_std::fs::write(err_path + "/config.txt", format!("{:#?}\n", config)).await?;
+    Ok(())
+}
+
+// saves why process was killed by health checks to health_check.txt
+async fn save_health_failure(reason: &str, err_path: String) -> Result<()> {
+    std::fs::create_dir_all(&err_path)?;
+    async_std::fs::write(err_path + "/health_check.txt", format!("{}\n", reason)).await?;
+    Ok(())
+}
+
 // created full command array from command and arguments
 fn create_command<'a>(command: &'a str, args: &'a [String]) -> Vec<&'a str> {
     let mut res = vec![command];
@@ -113,4 +418,142 @@ mod tests {
         let args = Vec::new();
         assert_eq!(create_command(command, &args), [String::from("test")]);
     }
+
+    #[test]
+    fn test_create_environment() {
+        let mut config = CommandConfig::parse_config(&serde_json::json!({
+            "command": "./server",
+            "env": { "RUNNER_TEST_VARIABLE": "value" },
+            "clear env": true
+        }))
+        .unwrap();
+        assert_eq!(
+            create_environment(&config),
+            vec![(
+                OsString::from("RUNNER_TEST_VARIABLE"),
+                OsString::from("value")
+            )]
+        );
+
+        config.clear_env = false;
+        let env = create_environment(&config);
+        assert!(env.len() > 1);
+        assert_eq!(
+            env.last(),
+            Some(&(
+                OsString::from("RUNNER_TEST_VARIABLE"),
+                OsString::from("value")
+            ))
+        );
+    }
+    #[test]
+    fn test_crash_manifest() {
+        let config = CommandConfig::parse_config(&serde_json::json!({
+            "command": "./server",
+            "args": ["--port", "80"],
+            "cwd": "/srv"
+        }))
+        .unwrap();
+        let env = vec![
+            (OsString::from("B"), OsString::from("2")),
+            (OsString::from("A"), OsString::from("1")),
+        ];
+        let start = Utc::now();
+        let report = CrashReport {
+            config: &config,
+            env: &env,
+            exit_status: ExitStatus::from_raw(9),
+            start,
+            end: start + chrono::Duration::milliseconds(1500),
+            pid: 42,
+            run: RunInfo {
+                restart: 3,
+                stage: 1,
+                recent_crashes: 2,
+                action: false,
+            },
+            health_failure: Some("Health check tcp port 80 failed 3 times in a row"),
+            limit_violation: Some("Memory 2.0 GB over limit 1.0 GB"),
+            resources: &[ResourceUsage {
+                time: start,
+                cpu: 12.5,
+                memory: 4096,
+                threads: 3,
+                open_files: 8,
+            }],
+        };
+        let manifest = crash_manifest(&report);
+        assert_eq!(manifest["command"], "./server");
+        assert_eq!(manifest["args"], serde_json::json!(["--port", "80"]));
+        assert_eq!(manifest["cwd"], "/srv");
+        assert_eq!(manifest["exit code"], serde_json::Value::Null);
+        assert_eq!(manifest["signal"], 9);
+        assert_eq!(manifest["uptime ms"], 1500);
+        assert_eq!(manifest["pid"], 42);
+        assert_eq!(manifest["restart"], 3);
+        assert_eq!(manifest["backup strategy stage"], 1);
+        assert_eq!(
+            manifest["health check"],
+            "Health check tcp port 80 failed 3 times in a row"
+        );
+        assert_eq!(
+            manifest["resource limit"],
+            "Memory 2.0 GB over limit 1.0 GB"
+        );
+        assert_eq!(manifest["resources"][0]["cpu percent"], 12.5);
+        assert_eq!(manifest["resources"][0]["memory bytes"], 4096);
+        assert_eq!(manifest["resources"][0]["open files"], 8);
+
+        // hash does not depend on variable order
+        let reversed: Vec<_> = env.iter().rev().cloned().collect();
+        assert_eq!(hash_environment(&env), hash_environment(&reversed));
+        assert_ne!(hash_environment(&env), hash_environment(&env[..1]));
+
+        let report = CrashReport {
+            exit_status: ExitStatus::from_raw(3 << 8),
+            health_failure: None,
+            ..report
+        };
+        let manifest = crash_manifest(&report);
+        assert_eq!(manifest["exit code"], 3);
+        assert_eq!(manifest["signal"], serde_json::Value::Null);
+        assert_eq!(manifest["health check"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_process_settings() {
+        // nice value is absolute, and only privileged process can go below its current one
+        let current = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+        let nice = current.max(5);
+        let config = CommandConfig::parse_config(&serde_json::json!({
+            "command": "sh",
+            "args": ["-c", "ulimit -n; ulimit -c; umask; nice"],
+            "rlimits": { "nofile": 64, "core": 0 },
+            "nice": nice,
+            "umask": "027"
+        }))
+        .unwrap();
+        let (process, _) = run(&config, create_environment(&config)).unwrap();
+        let output = process.wait_with_output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!("64\n0\n0027\n{}\n", nice)
+        );
+
+        // raising priority and realtime io class need privileges
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let config = CommandConfig::parse_config(&serde_json::json!({
+            "command": "nice",
+            "nice": -5,
+            "ionice": { "class": "realtime", "level": 7 }
+        }))
+        .unwrap();
+        let (process, _) = run(&config, create_environment(&config)).unwrap();
+        let output = process.wait_with_output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "-5\n");
+    }
 }
@@ -15,76 +15,159 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <http://www.gnu.org/licenses/>.
 */
 
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
 use async_std::{
     channel::{self, Sender},
     task,
 };
-use chrono::Utc;
 use futures::future::join_all;
 
 use crate::{
+    backoff::Backoff,
     command_config::{CommandConfig, CommandMode},
+    command_log::CommandLog,
     config::Config,
+    control::Control,
+    crash_store::CrashStore,
+    escalation::{Escalation, EscalationLadder},
+    run_command::RunInfo,
     runner_error::Result,
-    tui_state::TuiEvent,
+    shutdown::Shutdown,
+    tui_state::{ProcessState, TuiEvent},
 };
 
-// main run called from main function
-pub(crate) async fn run(config: String) -> Result<()> {
+// number of events waiting to be handled before senders wait, or output lines are dropped from
+// the interface
+const EVENT_CAPACITY: usize = 4096usize;
+
+// main run called from main function. In headless mode output is printed as lines instead of tui
+pub(crate) async fn run(config: String, headless: bool) -> Result<()> {
     // parse config file
     let config = Config::create(config)?;
+    let names: Vec<String> = config
+        .commands
+        .iter()
+        .map(|command| command.name.clone())
+        .collect();
 
-    let (tx, rx) = channel::unbounded();
-    let tui_handle = task::spawn(crate::tui::run(tx.clone(), rx));
+    let shutdown = Arc::new(Shutdown::new(&config.start_order));
+    let control = Arc::new(Control::new(
+        names.clone(),
+        shutdown.clone(),
+        CrashStore::new(&config),
+    ));
+
+    // install signal handlers and bind control socket before starting the interface, so that
+    // failures are reported on a usable terminal
+    crate::shutdown::monitor_signals(shutdown.clone())?;
+    let listener = match &config.control_socket {
+        Some(socket) => Some(crate::control_server::bind(socket).await?),
+        None => None,
+    };
+
+    let (tx, rx) = channel::bounded(EVENT_CAPACITY);
+    let (ui_tx, ui_rx) = channel::bounded(EVENT_CAPACITY);
+    let logs = config
+        .commands
+        .iter()
+        .map(|command| command.log.clone().map(CommandLog::new))
+        .collect();
+    task::spawn(crate::control::forward_events(
+        rx,
+        ui_tx,
+        control.clone(),
+        logs,
+        !headless,
+    ));
+    let tui_handle = if headless {
+        task::spawn(crate::headless::run(ui_rx))
+    } else {
+        task::spawn(crate::tui::run(tx.clone(), ui_rx, shutdown.clone()))
+    };
+    if let Some(listener) = listener {
+        task::spawn(crate::control_server::run(
+            listener,
+            control.clone(),
+            tx.clone(),
+        ));
+    }
 
-    tx.try_send(TuiEvent::TabListChanged(
-        config
-            .commands
-            .iter()
-            .map(|command| command.name.clone())
-            .collect(),
-    ))?;
+    let tabs = config
+        .commands
+        .iter()
+        .map(|command| (command.name.clone(), command.scrollback))
+        .collect();
+    tx.send(TuiEvent::TabListChanged(tabs)).await?;
 
     //ensure error folder exists
     std::fs::create_dir_all(&config.crash_path)?;
+    control.crash_store().clean().await;
 
-    // execute all commands, saving handles
-    execute_commands(config, tx).await?;
+    // execute all commands in the background until shutdown is requested
+    let control_socket = config.control_socket.clone();
+    let commands_handle = task::spawn({
+        let tx = tx.clone();
+        let shutdown = shutdown.clone();
+        async move {
+            let result = execute_commands(config, tx, control).await;
+            // commands after the failed one are never started, so we shut down and report it
+            if result.is_err() {
+                shutdown.request();
+            }
+            result
+        }
+    });
+    shutdown.wait_for_request().await;
+    shutdown.stop_all(&tx).await?;
+    let result = commands_handle.await;
+    if let Some(socket) = control_socket {
+        let _ = std::fs::remove_file(socket);
+    }
 
-    tui_handle.await
+    // let tui restore the terminal before reporting any errors
+    tx.send(TuiEvent::Exit).await?;
+    tui_handle.await?;
+    result.map(|_| ())
 }
 
-// executes command based on mode
-async fn execute_commands(config: Config, tx: Sender<TuiEvent>) -> Result<Vec<()>> {
+// starts supervising all commands in dependency order, waiting for commands that should be waited
+// for. Commands without dependencies between them are started in parallel
+async fn execute_commands(
+    config: Config,
+    tx: Sender<TuiEvent>,
+    control: Arc<Control>,
+) -> Result<Vec<()>> {
     let mut futures = Vec::new();
+    let mut commands: Vec<Option<CommandConfig>> = config.commands.into_iter().map(Some).collect();
 
-    for (id, command) in config.commands.into_iter().enumerate() {
-        match command.mode {
-            CommandMode::RunOnce => futures.push(task::spawn(run_once(
-                command,
-                config.crash_path.clone(),
-                tx.clone(),
-                id,
-            ))),
-            CommandMode::RunOnceAndWait => {
-                run_once(command, config.crash_path.clone(), tx.clone(), id).await?
-            }
-            CommandMode::RunUntilSuccess => futures.push(task::spawn(run_until_success(
-                command,
-                config.crash_path.clone(),
-                tx.clone(),
-                id,
-            ))),
-            CommandMode::RunUntilSuccessAndWait => {
-                run_until_success(command, config.crash_path.clone(), tx.clone(), id).await?
-            }
-            CommandMode::KeepAlive => futures.push(task::spawn(run_keep_alive(
-                command,
-                config.crash_path.clone(),
-                tx.clone(),
-                id,
-            ))),
+    for id in config.start_order {
+        let command = match commands[id].take() {
+            Some(command) => command,
+            None => continue,
         };
+        let wait = matches!(
+            command.mode,
+            CommandMode::RunOnceAndWait | CommandMode::RunUntilSuccessAndWait
+        );
+        let (first_run_tx, first_run_rx) = channel::bounded(1);
+        futures.push(task::spawn(supervise(
+            command,
+            config.dependencies[id].clone(),
+            config.crash_path.clone(),
+            tx.clone(),
+            id,
+            control.clone(),
+            wait.then_some(first_run_tx),
+        )));
+        if wait {
+            if let Ok(result) = first_run_rx.recv().await {
+                result?;
+            }
+        }
     }
     join_all(futures)
         .await
@@ -92,131 +175,386 @@ async fn execute_commands(config: Config, tx: Sender<TuiEvent>) -> Result<Vec<()
         .collect::<Result<Vec<()>>>()
 }
 
+// supervises single command. Once its dependencies are satisfied, runs it according to its mode,
+// after which it can be started again through control socket. Result of first run is sent through
+// first run channel if given, otherwise it is returned once runner shuts down
+async fn supervise(
+    command: CommandConfig,
+    dependencies: Vec<usize>,
+    error_path: String,
+    tx: Sender<TuiEvent>,
+    id: usize,
+    control: Arc<Control>,
+    mut first_run_tx: Option<Sender<Result<()>>>,
+) -> Result<()> {
+    let mut first_result = None;
+    loop {
+        if !control
+            .wait_for_dependencies(id, &dependencies, &tx)
+            .await?
+        {
+            return first_result.unwrap_or(Ok(()));
+        }
+        control.set_running(id);
+        let result = run_mode(&command, error_path.clone(), tx.clone(), id, &control).await;
+        control.set_finished(id);
+        let state = match &result {
+            _ if control.should_stop(id) => ProcessState::Stopped,
+            Ok(state) => *state,
+            Err(_) => ProcessState::Finished,
+        };
+        tx.send(TuiEvent::StateChanged(id, state)).await?;
+        let result = result.map(|_| ());
+        if let Some(first_run_tx) = first_run_tx.take() {
+            first_run_tx.try_send(result).ok();
+        } else if first_result.is_none() {
+            first_result = Some(result);
+        } else if let Err(err) = result {
+            tx.send(TuiEvent::NewSystemMessage(id, err.to_string()))
+                .await?;
+        }
+
+        if !control.wait_for_start(id).await {
+            return first_result.unwrap_or(Ok(()));
+        }
+    }
+}
+
+// runs command once according to its mode. Returns whether runner finished or gave up on it
+async fn run_mode(
+    command: &CommandConfig,
+    error_path: String,
+    tx: Sender<TuiEvent>,
+    id: usize,
+    control: &Control,
+) -> Result<ProcessState> {
+    match command.mode {
+        CommandMode::RunOnce | CommandMode::RunOnceAndWait => {
+            run_once(command, error_path, tx, id, control).await?;
+            if !control.should_stop(id) {
+                control.set_satisfied(id);
+            }
+            Ok(ProcessState::Finished)
+        }
+        CommandMode::RunUntilSuccess | CommandMode::RunUntilSuccessAndWait => {
+            run_until_success(command, error_path, tx, id, control).await
+        }
+        CommandMode::KeepAlive => run_keep_alive(command, error_path, tx, id, control).await,
+    }
+}
+
 // run once
 async fn run_once(
-    command: CommandConfig,
+    command: &CommandConfig,
     error_path: String,
     tx: Sender<TuiEvent>,
     id: usize,
+    control: &Control,
 ) -> Result<()> {
-    crate::run_command::run_command(&command, error_path, tx, id).await
+    crate::run_command::run_command(command, error_path, tx, id, control, RunInfo::default()).await
 }
 
 // run until success (exit code 0)
 async fn run_until_success(
-    command: CommandConfig,
+    command: &CommandConfig,
     error_path: String,
     tx: Sender<TuiEvent>,
     id: usize,
-) -> Result<()> {
-    let mut crashes = Vec::new();
-    while crate::run_command::run_command(&command, error_path.clone(), tx.clone(), id)
-        .await
-        .is_err()
-    {
-        crashes.push(Utc::now());
-        if let Some(strategy) = &command.backup_strategy {
-            let mut crash_count = 0u64;
-            for timestamp in &crashes {
-                if timestamp > &(Utc::now() - strategy.period) {
-                    crash_count += 1u64;
-                }
-                if crash_count > strategy.times {
-                    if strategy.script.is_none() && strategy.safe_mode.is_none() {
-                        // we have no handling strategy so we just give up
-                        tx.try_send(TuiEvent::NewStderrMessage(
-                            id,
-                            String::from(
-                                "Crash limit reached with no handling strategy, giving up!",
-                            ),
-                        ))?;
-                        return Ok(());
-                    }
-                    if let Some(script) = &strategy.script {
-                        let script_config = CommandConfig {
-                            command: script.to_owned(),
-                            args: Vec::new(),
-                            stdout_history: command.stdout_history,
-                            mode: CommandMode::RunOnceAndWait,
-                            name: script.to_owned(),
-                            backup_strategy: None,
-                        };
-                        run_once(script_config, error_path.clone(), tx.clone(), id).await?
-                    }
-                    if let Some(args) = &strategy.safe_mode {
-                        let script_config = CommandConfig {
-                            command: command.command.to_owned(),
-                            args: args.clone(),
-                            stdout_history: command.stdout_history,
-                            mode: CommandMode::RunOnceAndWait,
-                            name: command.name.to_owned(),
-                            backup_strategy: None,
-                        };
-                        run_once(script_config, error_path.clone(), tx.clone(), id).await?
-                    }
-                }
-            }
+    control: &Control,
+) -> Result<ProcessState> {
+    let mut ladder = EscalationLadder::new(command);
+    let mut backoff = Backoff::new(&command.restart);
+    for restart in 0u64.. {
+        let started = Instant::now();
+        let run_info = RunInfo {
+            restart,
+            stage: ladder.stage(),
+            recent_crashes: ladder.recent_crashes(),
+            action: false,
+        };
+        let result = crate::run_command::run_command(
+            ladder.command(),
+            error_path.clone(),
+            tx.clone(),
+            id,
+            control,
+            run_info,
+        )
+        .await;
+        // backup strategy actions take time, which should not count as uptime of the process
+        let uptime = started.elapsed();
+        de_escalate(&mut ladder, uptime, &tx, id).await?;
+        if result.is_ok() || control.should_stop(id) {
+            break;
+        }
+        if !handle_crash(command, &mut ladder, &error_path, &tx, id, control).await? {
+            return Ok(ProcessState::GivenUp);
+        }
+        if !wait_before_restart(&mut backoff, uptime, &tx, id, control).await? {
+            break;
         }
     }
-    Ok(())
+    if !control.should_stop(id) {
+        control.set_satisfied(id);
+    }
+    Ok(ProcessState::Finished)
 }
 
 // keep alive, ignoring exit codes
 async fn run_keep_alive(
-    command: CommandConfig,
+    command: &CommandConfig,
     error_path: String,
     tx: Sender<TuiEvent>,
     id: usize,
-) -> Result<()> {
-    let mut crashes = Vec::new();
-    loop {
-        if crate::run_command::run_command(&command, error_path.clone(), tx.clone(), id)
-            .await
-            .is_err()
+    control: &Control,
+) -> Result<ProcessState> {
+    let mut ladder = EscalationLadder::new(command);
+    let mut backoff = Backoff::new(&command.restart);
+    let mut restart = 0u64;
+    while !control.should_stop(id) {
+        let started = Instant::now();
+        let run_info = RunInfo {
+            restart,
+            stage: ladder.stage(),
+            recent_crashes: ladder.recent_crashes(),
+            action: false,
+        };
+        let result = crate::run_command::run_command(
+            ladder.command(),
+            error_path.clone(),
+            tx.clone(),
+            id,
+            control,
+            run_info,
+        )
+        .await;
+        restart += 1;
+        // backup strategy actions take time, which should not count as uptime of the process
+        let uptime = started.elapsed();
+        de_escalate(&mut ladder, uptime, &tx, id).await?;
+        if result.is_err()
+            && !control.should_stop(id)
+            && !handle_crash(command, &mut ladder, &error_path, &tx, id, control).await?
         {
-            crashes.push(Utc::now());
-            if let Some(strategy) = &command.backup_strategy {
-                let mut crash_count = 0u64;
-                for timestamp in &crashes {
-                    if timestamp > &(Utc::now() - strategy.period) {
-                        crash_count += 1u64;
-                    }
-                    if crash_count > strategy.times {
-                        if strategy.script.is_none() && strategy.safe_mode.is_none() {
-                            // we have no handling strategy so we just give up
-                            tx.try_send(TuiEvent::NewStderrMessage(
-                                id,
-                                String::from(
-                                    "Crash limit reached with no handling strategy, giving up!",
-                                ),
-                            ))?;
-                            return Ok(());
-                        }
-                        if let Some(script) = &strategy.script {
-                            let script_config = CommandConfig {
-                                command: script.to_owned(),
-                                args: Vec::new(),
-                                stdout_history: command.stdout_history,
-                                mode: CommandMode::RunOnceAndWait,
-                                name: script.to_owned(),
-                                backup_strategy: None,
-                            };
-                            run_once(script_config, error_path.clone(), tx.clone(), id).await?
-                        }
-                        if let Some(args) = &strategy.safe_mode {
-                            let script_config = CommandConfig {
-                                command: command.command.to_owned(),
-                                args: args.clone(),
-                                stdout_history: command.stdout_history,
-                                mode: CommandMode::RunOnceAndWait,
-                                name: command.name.to_owned(),
-                                backup_strategy: None,
-                            };
-                            run_once(script_config, error_path.clone(), tx.clone(), id).await?
-                        }
-                    }
-                }
-            }
+            return Ok(ProcessState::GivenUp);
         }
+        if !wait_before_restart(&mut backoff, uptime, &tx, id, control).await? {
+            break;
+        }
+    }
+    Ok(ProcessState::Finished)
+}
+
+// records crash and runs actions of backup strategy stage once its crash limit is exceeded.
+// Returns false if runner should give up on the command
+async fn handle_crash(
+    command: &CommandConfig,
+    ladder: &mut EscalationLadder<'_>,
+    error_path: &str,
+    tx: &Sender<TuiEvent>,
+    id: usize,
+    control: &Control,
+) -> Result<bool> {
+    let (number, stage) = match ladder.record_crash() {
+        Escalation::Restart => return Ok(true),
+        Escalation::Stage(number, stage) => (number, stage),
+    };
+    tx.send(TuiEvent::NewSystemMessage(
+        id,
+        format!(
+            "Crash limit reached, escalating to backup strategy stage {}",
+            number
+        ),
+    ))
+    .await?;
+    if let Some(script) = &stage.script {
+        let script_config = command.derive(script.to_owned(), Vec::new(), script.to_owned());
+        run_action(&script_config, error_path, tx, id, control).await?
+    }
+    if let Some(args) = &stage.safe_mode {
+        let safe_config = command.derive(
+            command.command.to_owned(),
+            args.clone(),
+            command.name.to_owned(),
+        );
+        run_action(&safe_config, error_path, tx, id, control).await?
+    }
+    if let Some((fallback, _)) = &stage.fallback {
+        tx.send(TuiEvent::NewSystemMessage(
+            id,
+            format!("Switching to fallback command {}", fallback),
+        ))
+        .await?;
+    }
+    if stage.give_up {
+        tx.send(TuiEvent::NewSystemMessage(
+            id,
+            String::from("Backup strategy exhausted, giving up!"),
+        ))
+        .await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// runs backup strategy action once. Failed action is reported, so runner can keep escalating
+async fn run_action(
+    config: &CommandConfig,
+    error_path: &str,
+    tx: &Sender<TuiEvent>,
+    id: usize,
+    control: &Control,
+) -> Result<()> {
+    let run_info = RunInfo {
+        action: true,
+        ..RunInfo::default()
+    };
+    let result = crate::run_command::run_command(
+        config,
+        error_path.to_owned(),
+        tx.clone(),
+        id,
+        control,
+        run_info,
+    )
+    .await;
+    if let Err(err) = result {
+        tx.send(TuiEvent::NewSystemMessage(
+            id,
+            format!("Backup strategy action {} failed: {}", config.command, err),
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+// goes back to first backup strategy stage if process stayed up long enough
+async fn de_escalate(
+    ladder: &mut EscalationLadder<'_>,
+    uptime: Duration,
+    tx: &Sender<TuiEvent>,
+    id: usize,
+) -> Result<()> {
+    if ladder.ran_for(uptime) {
+        tx.send(TuiEvent::NewSystemMessage(
+            id,
+            String::from("Command stayed up, de-escalating to first backup strategy stage"),
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+// waits before restarting process that was up for given time, letting interface show countdown.
+// Returns false if command was stopped instead
+async fn wait_before_restart(
+    backoff: &mut Backoff<'_>,
+    uptime: Duration,
+    tx: &Sender<TuiEvent>,
+    id: usize,
+    control: &Control,
+) -> Result<bool> {
+    if control.should_stop(id) {
+        return Ok(false);
+    }
+    let delay = backoff.next_delay(uptime);
+    if delay.is_zero() {
+        return Ok(true);
+    }
+    tx.send(TuiEvent::RestartScheduled(id, delay)).await?;
+    Ok(control.sleep(id, delay).await)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use async_std::prelude::FutureExt;
+    use serde_json::json;
+
+    use super::*;
+
+    // creates control for a single command
+    fn create_control(command: &CommandConfig) -> Control {
+        Control::for_test(&[&command.name])
+    }
+
+    #[test]
+    fn test_handle_crash() {
+        let command = CommandConfig::parse_config(&json!({
+            "command": "false",
+            "backup strategy": [
+                { "times": 1, "period": "1h", "give up": false },
+                { "times": 0, "period": "1h", "script": "true" },
+                { "times": 0, "period": "1h", "script": "true", "give up": true }
+            ]
+        }))
+        .unwrap();
+        let control = create_control(&command);
+        let (tx, rx) = channel::bounded(EVENT_CAPACITY);
+        let mut ladder = EscalationLadder::new(&command);
+        let mut crash =
+            || task::block_on(handle_crash(&command, &mut ladder, "", &tx, 0, &control));
+
+        // stage without actions keeps escalating unless it says to give up
+        assert!(crash().unwrap());
+        assert!(crash().unwrap());
+        assert!(crash().unwrap());
+        assert!(!crash().unwrap());
+        assert_eq!(ladder.stage(), 3);
+        let messages: Vec<String> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|event| match event {
+                TuiEvent::NewSystemMessage(_, message) => Some(message),
+                _ => None,
+            })
+            .filter(|message| message.contains("giving up"))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![String::from("Backup strategy exhausted, giving up!")]
+        );
+
+        // stage without actions gives up by default
+        let command = CommandConfig::parse_config(&json!({
+            "command": "false",
+            "backup strategy": { "times": 0, "period": "1h" }
+        }))
+        .unwrap();
+        let mut ladder = EscalationLadder::new(&command);
+        assert!(
+            !task::block_on(handle_crash(&command, &mut ladder, "", &tx, 0, &control)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_restart_during_backoff() {
+        let command = CommandConfig::parse_config(&json!({
+            "command": "false",
+            "restart delay": "1h",
+            "jitter": 0
+        }))
+        .unwrap();
+        let control = Arc::new(create_control(&command));
+        let (tx, _rx) = channel::bounded(EVENT_CAPACITY);
+        control.set_running(0);
+
+        let waiting = task::spawn({
+            let control = control.clone();
+            let tx = tx.clone();
+            async move {
+                let mut backoff = Backoff::new(&command.restart);
+                wait_before_restart(&mut backoff, Duration::ZERO, &tx, 0, &control).await
+            }
+        });
+        task::block_on(async {
+            task::sleep(Duration::from_millis(200)).await;
+            control.restart(0, &tx).await.unwrap();
+            let restarted = waiting.timeout(Duration::from_secs(1)).await;
+            assert!(!restarted.unwrap().unwrap());
+
+            // restart is still pending, so loop of the command does not continue on its own
+            assert!(control.should_stop(0));
+            assert!(control.wait_for_start(0).await);
+            assert!(!control.should_stop(0));
+        });
     }
 }
@@ -15,9 +15,9 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <http://www.gnu.org/licenses/>.
 */
 
-use subprocess::PopenError;
+use std::process::ExitStatus;
 
-use crate::config_error::ConfigError;
+use crate::{config_error::ConfigError, tui_state::exit_message};
 
 pub(crate) type Result<T> = std::result::Result<T, RunnerError>;
 
@@ -27,11 +27,12 @@ pub(crate) enum RunnerError {
     MissingConfiguration,
     ConfigurationError(ConfigError),
     FileSystemError(std::io::Error),
-    ExitError(subprocess::ExitStatus),
-    ProcessError(PopenError),
+    ExitError(ExitStatus),
+    ProcessError(std::io::Error),
     ChannelError(async_std::channel::TrySendError<crate::tui_state::TuiEvent>),
     CannotGetStderr,
     CannotGetStdout,
+    ControlError(String),
 }
 
 impl std::fmt::Display for RunnerError {
@@ -42,11 +43,14 @@ impl std::fmt::Display for RunnerError {
                 write!(f, "Error parsing configuration: {}", err)
             }
             RunnerError::FileSystemError(err) => write!(f, "File system error: {}", err),
-            RunnerError::ExitError(err) => write!(f, "Process exited with: {:#?}", err),
+            RunnerError::ExitError(err) => {
+                write!(f, "Process exited with: {}", exit_message(*err))
+            }
             RunnerError::ProcessError(err) => write!(f, "Error creating process: {}", err),
             RunnerError::ChannelError(err) => write!(f, "Unexpected channel error: {}", err),
             RunnerError::CannotGetStderr => write!(f, "Could not get Stderr for a process!"),
             RunnerError::CannotGetStdout => write!(f, "Could not get Stdout for a process!"),
+            RunnerError::ControlError(err) => write!(f, "Control error: {}", err),
         }
     }
 }
@@ -65,13 +69,6 @@ impl std::convert::From<std::io::Error> for RunnerError {
     }
 }
 
-// process error conversion
-impl std::convert::From<PopenError> for RunnerError {
-    fn from(process_error: PopenError) -> Self {
-        RunnerError::ProcessError(process_error)
-    }
-}
-
 // channel error conversion
 impl std::convert::From<async_std::channel::TrySendError<crate::tui_state::TuiEvent>>
     for RunnerError
@@ -80,3 +77,10 @@ impl std::convert::From<async_std::channel::TrySendError<crate::tui_state::TuiEv
         RunnerError::ChannelError(channel_error)
     }
 }
+
+// blocking channel send fails only once channel is closed
+impl std::convert::From<async_std::channel::SendError<crate::tui_state::TuiEvent>> for RunnerError {
+    fn from(channel_error: async_std::channel::SendError<crate::tui_state::TuiEvent>) -> Self {
+        RunnerError::ChannelError(async_std::channel::TrySendError::Closed(channel_error.0))
+    }
+}
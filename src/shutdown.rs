@@ -0,0 +1,284 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use async_std::{
+    channel::{self, Receiver, Sender},
+    task,
+};
+use signal_hook::{consts::TERM_SIGNALS, iterator::Signals};
+
+use crate::{runner_error::Result, tui_state::TuiEvent};
+
+// how often we check whether a stopped process has exited
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// process currently running under supervision
+struct LiveProcess {
+    // command id
+    id: usize,
+
+    // process id, also process group id of the process
+    pid: u32,
+
+    // signal to send when asking process to stop
+    stop_signal: i32,
+
+    // time to wait for process to exit before killing it
+    stop_timeout: Duration,
+}
+
+// shared state of shutdown coordinator
+#[derive(Default)]
+struct ShutdownState {
+    // set once shutdown was requested. No new processes should be started after that
+    requested: bool,
+
+    // live processes in the order they were started
+    processes: Vec<LiveProcess>,
+}
+
+// Coordinates shutdown of all supervised processes
+pub(crate) struct Shutdown {
+    state: Mutex<ShutdownState>,
+    request_tx: Sender<()>,
+    request_rx: Receiver<()>,
+
+    // position of every command in start order, dependencies before dependents
+    start_ranks: Vec<usize>,
+}
+
+impl Shutdown {
+    // creates coordinator with no running processes for commands started in given order
+    pub(crate) fn new(start_order: &[usize]) -> Shutdown {
+        let (request_tx, request_rx) = channel::bounded(1);
+        let mut start_ranks = vec![0; start_order.len()];
+        for (rank, &id) in start_order.iter().enumerate() {
+            start_ranks[id] = rank;
+        }
+        Shutdown {
+            state: Mutex::new(ShutdownState::default()),
+            request_tx,
+            request_rx,
+            start_ranks,
+        }
+    }
+
+    // asks runner to shut down. Can be called multiple times
+    pub(crate) fn request(&self) {
+        self.lock().requested = true;
+        // closing the channel wakes up everyone waiting for shutdown
+        self.request_tx.close();
+    }
+
+    // whether shutdown was requested
+    pub(crate) fn is_requested(&self) -> bool {
+        self.lock().requested
+    }
+
+    // waits until shutdown is requested
+    pub(crate) async fn wait_for_request(&self) {
+        while self.request_rx.recv().await.is_ok() {}
+    }
+
+    // registers newly started process. Process started after shutdown was requested is killed right away
+    pub(crate) fn register(&self, id: usize, pid: u32, stop_signal: i32, stop_timeout: Duration) {
+        let mut state = self.lock();
+        if state.requested {
+            send_signal(pid, libc::SIGKILL);
+        }
+        state.processes.push(LiveProcess {
+            id,
+            pid,
+            stop_signal,
+            stop_timeout,
+        });
+    }
+
+    // removes process that has exited and was waited for
+    pub(crate) fn unregister(&self, pid: u32) {
+        self.lock().processes.retain(|process| process.pid != pid);
+    }
+
+    // process id of a live process started for command, if any
+    pub(crate) fn pid(&self, id: usize) -> Option<u32> {
+        self.lock()
+            .processes
+            .iter()
+            .rev()
+            .find(|process| process.id == id)
+            .map(|process| process.pid)
+    }
+
+    // kills process right away, without giving it a chance to stop gracefully
+    pub(crate) fn kill(&self, pid: u32) {
+        send_signal(pid, libc::SIGKILL);
+    }
+
+    // kills process groups of all live processes right away
+    pub(crate) fn kill_all(&self) {
+        for process in &self.lock().processes {
+            send_signal(process.pid, libc::SIGKILL);
+        }
+    }
+
+    // stops all live processes, dependents before their dependencies and otherwise in reverse
+    // start order. Each process gets its stop signal and is killed if it does not exit in its
+    // stop timeout
+    pub(crate) async fn stop_all(&self, tx: &Sender<TuiEvent>) -> Result<()> {
+        self.request();
+        self.stop_processes(tx, |_| true).await
+    }
+
+    // stops all live processes of a single command, the same way as on shutdown
+    pub(crate) async fn stop_command(&self, id: usize, tx: &Sender<TuiEvent>) -> Result<()> {
+        self.stop_processes(tx, |process_id| process_id == id).await
+    }
+
+    // stops selected live processes in reverse dependency order
+    async fn stop_processes(
+        &self,
+        tx: &Sender<TuiEvent>,
+        selected: impl Fn(usize) -> bool,
+    ) -> Result<()> {
+        let mut processes = self
+            .lock()
+            .processes
+            .iter()
+            .rev()
+            .filter(|process| selected(process.id))
+            .map(|process| {
+                (
+                    process.id,
+                    process.pid,
+                    process.stop_signal,
+                    process.stop_timeout,
+                )
+            })
+            .collect::<Vec<_>>();
+        // stable sort keeps reverse start order between processes of the same rank
+        processes.sort_by_key(|(id, ..)| std::cmp::Reverse(self.rank(*id)));
+
+        for (id, pid, stop_signal, stop_timeout) in processes {
+            tx.send(TuiEvent::NewSystemMessage(
+                id,
+                String::from("Stopping command"),
+            ))
+            .await?;
+            send_signal(pid, stop_signal);
+            if !self.wait_for_exit(pid, stop_timeout).await {
+                tx.send(TuiEvent::NewSystemMessage(
+                    id,
+                    String::from("Command did not stop in time, killing it"),
+                ))
+                .await?;
+                send_signal(pid, libc::SIGKILL);
+            }
+        }
+        Ok(())
+    }
+
+    // waits for process to be unregistered. Returns false if it is still running after timeout
+    async fn wait_for_exit(&self, pid: u32, timeout: Duration) -> bool {
+        let mut waited = Duration::ZERO;
+        while self.is_running(pid) {
+            if waited >= timeout {
+                return false;
+            }
+            task::sleep(EXIT_POLL_INTERVAL).await;
+            waited += EXIT_POLL_INTERVAL;
+        }
+        true
+    }
+
+    // position of command in start order. Processes of unknown commands are stopped first
+    fn rank(&self, id: usize) -> usize {
+        self.start_ranks.get(id).copied().unwrap_or(usize::MAX)
+    }
+
+    // whether process is still registered
+    fn is_running(&self, pid: u32) -> bool {
+        self.lock()
+            .processes
+            .iter()
+            .any(|process| process.pid == pid)
+    }
+
+    // locks state
+    fn lock(&self) -> MutexGuard<'_, ShutdownState> {
+        crate::lock::lock(&self.state)
+    }
+}
+
+// requests shutdown on SIGINT, SIGTERM or SIGQUIT, and kills remaining processes right away on
+// any further signal. Signal handlers are installed before returning, so failing to install them
+// is reported right away
+pub(crate) fn monitor_signals(shutdown: Arc<Shutdown>) -> Result<()> {
+    let mut signals = Signals::new(TERM_SIGNALS)?;
+    task::spawn_blocking(move || {
+        for (received, _) in signals.forever().enumerate() {
+            if received == 0 {
+                shutdown.request();
+            } else {
+                shutdown.kill_all();
+            }
+        }
+    });
+    Ok(())
+}
+
+// sends signal to entire process group of a process
+fn send_signal(pid: u32, signal: i32) {
+    // processes are started in their own process group so group id matches process id
+    unsafe {
+        libc::kill(-(pid as i32), signal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+    use super::*;
+
+    #[test]
+    fn test_kill_all() {
+        let shutdown = Shutdown::new(&[0, 1]);
+        let mut children: Vec<std::process::Child> = (0..2)
+            .map(|_| {
+                std::process::Command::new("sleep")
+                    .arg("10")
+                    .process_group(0)
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+        for (id, child) in children.iter().enumerate() {
+            shutdown.register(id, child.id(), libc::SIGTERM, Duration::from_secs(10));
+        }
+
+        shutdown.kill_all();
+        for child in &mut children {
+            assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGKILL));
+        }
+    }
+}
@@ -0,0 +1,51 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+
+// empty temporary folder for a single test. Folder is removed once dropped, so it is cleaned up
+// even when test fails
+pub(crate) struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    // creates folder unique to given test name and test process
+    pub(crate) fn new(name: &str) -> TestDir {
+        let path =
+            std::env::temp_dir().join(format!("runner-{}-test-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    // path of the folder
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // path of an entry within the folder
+    pub(crate) fn join(&self, entry: impl AsRef<Path>) -> PathBuf {
+        self.path.join(entry)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
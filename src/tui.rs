@@ -17,7 +17,8 @@ along with this program. If not, see <http://www.gnu.org/licenses/>.
 
 use std::{
     io::{self, Stdout},
-    time::Duration,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use async_std::{
@@ -25,6 +26,7 @@ use async_std::{
     future::timeout,
     task,
 };
+use regex::Regex;
 use termion::{
     event::Key,
     input::{MouseTerminal, TermRead},
@@ -32,27 +34,54 @@ use termion::{
     screen::AlternateScreen,
 };
 use tui::{
-    backend::TermionBackend,
-    layout::{Constraint, Corner, Direction, Layout},
+    backend::{Backend, TermionBackend},
+    layout::{Constraint, Corner, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans},
-    widgets::{Block, Borders, List, ListItem, Tabs},
-    Terminal,
+    widgets::{
+        Block, Borders, Cell, List, ListItem, Paragraph, Row, Sparkline, Table, TableState, Tabs,
+    },
+    Frame, Terminal,
 };
 
 use crate::{
     runner_error::Result,
-    tui_state::{Severity, TabState, TuiEvent, TuiState},
+    shutdown::Shutdown,
+    tui_state::{
+        create_search, format_memory, readiness_message, restart_message, Filter, ProcessState,
+        Prompt, PromptKind, Severity, TabState, TuiEvent, TuiState,
+    },
 };
 
+// shortest time between redraws, so busy commands do not redraw screen for every line
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+// how often restart countdown, uptime and terminal size are checked while there are no events
+const IDLE_INTERVAL: Duration = Duration::from_millis(250);
+
+// widths of overview columns: name, state, uptime, restarts, last crash, cpu and memory
+const OVERVIEW_WIDTHS: [Constraint; 7] = [
+    Constraint::Min(12),
+    Constraint::Length(12),
+    Constraint::Length(9),
+    Constraint::Length(9),
+    Constraint::Length(20),
+    Constraint::Length(7),
+    Constraint::Length(10),
+];
+
 // terminal type to be passed around
 type TerminalT = Terminal<TermionBackend<AlternateScreen<MouseTerminal<RawTerminal<Stdout>>>>>;
 
 // tui thread. first to start, last to quit
-pub(crate) async fn run(tx: Sender<TuiEvent>, rx: Receiver<TuiEvent>) -> Result<()> {
-    task::spawn(start_key_monitoring_loop(tx));
-    start_display_loop(rx)?;
-    Ok(())
+pub(crate) async fn run(
+    tx: Sender<TuiEvent>,
+    rx: Receiver<TuiEvent>,
+    shutdown: Arc<Shutdown>,
+) -> Result<()> {
+    // both loops block on the terminal, so they get their own threads instead of stalling executor
+    task::spawn_blocking(move || start_key_monitoring_loop(tx));
+    task::spawn_blocking(move || start_display_loop(rx, &shutdown)).await
 }
 
 // draw on display
@@ -61,107 +90,516 @@ fn draw_screen(tui_state: &mut TuiState, terminal: &mut TerminalT) -> Result<()>
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .margin(1)
-            .constraints([Constraint::Length(3), Constraint::Min(0)].as_ref())
+            .constraints(
+                [
+                    Constraint::Length(3),
+                    Constraint::Length(if tui_state.overview.is_none() { 2 } else { 0 }),
+                    Constraint::Min(0),
+                    Constraint::Length(u16::from(tui_state.prompt.is_some())),
+                ]
+                .as_ref(),
+            )
             .split(f.size());
 
         let block = Block::default().style(Style::default().bg(Color::Black).fg(Color::Cyan));
         f.render_widget(block, f.size());
 
+        // output block has borders on both sides
+        tui_state.page = usize::from(chunks[2].height.saturating_sub(2));
+
         let tabs = create_tabs(tui_state);
         f.render_widget(tabs, chunks[0]);
 
-        let output = create_output(tui_state);
-        f.render_widget(output, chunks[1]);
+        if let Some(tab) = tui_state.tabs.get(tui_state.index) {
+            draw_status(f, tab, chunks[1]);
+        }
+
+        if let Some(selected) = tui_state.overview {
+            let mut table_state = TableState::default();
+            table_state.select(Some(selected));
+            f.render_stateful_widget(create_overview(tui_state), chunks[2], &mut table_state);
+        } else {
+            let output = create_output(tui_state);
+            f.render_widget(output, chunks[2]);
+        }
+
+        if let Some(prompt) = &tui_state.prompt {
+            f.render_widget(create_prompt(prompt), chunks[3]);
+        }
     })?;
     Ok(())
 }
 
 // monitor key presses from users
-async fn start_key_monitoring_loop(tx: Sender<TuiEvent>) -> Result<()> {
+fn start_key_monitoring_loop(tx: Sender<TuiEvent>) -> Result<()> {
     let stdin = io::stdin();
     for key in stdin.keys().flatten() {
-        tx.try_send(TuiEvent::Input(key))?;
+        task::block_on(tx.send(TuiEvent::Input(key)))?;
     }
     Ok(())
 }
 
-// react to the events
-fn start_display_loop(rx: Receiver<TuiEvent>) -> Result<()> {
+// how display reacts to an event
+enum Redraw {
+    // state changed, screen is redrawn with next frame
+    Later,
+
+    // user pressed a key, screen is redrawn right away
+    Now,
+
+    // runner exited
+    Quit,
+}
+
+// react to the events until runner exits. All waiting events are applied before screen is redrawn,
+// at most once per frame and only when something changed. Terminal is restored when it goes out
+// of scope
+fn start_display_loop(rx: Receiver<TuiEvent>, shutdown: &Shutdown) -> Result<()> {
     let mut tui_state = TuiState::build(Vec::new());
     let mut terminal = Terminal::new(TermionBackend::new(AlternateScreen::from(
         MouseTerminal::from(io::stdout().into_raw_mode()?),
     )))?;
+    draw_screen(&mut tui_state, &mut terminal)?;
+    let mut last_draw = Instant::now();
+    let mut size = terminal.size()?;
+    let mut changed = false;
     loop {
-        update_tui_state(&mut tui_state, &rx);
-        draw_screen(&mut tui_state, &mut terminal)?;
+        let interval = if changed {
+            FRAME_INTERVAL
+        } else {
+            IDLE_INTERVAL
+        };
+        let first = match task::block_on(timeout(
+            interval.saturating_sub(last_draw.elapsed()),
+            rx.recv(),
+        )) {
+            Ok(Ok(event)) => Some(event),
+            Ok(Err(_)) => return Ok(()),
+            Err(_) => None,
+        };
+        // events arriving while these are applied wait for next frame
+        let waiting = rx.len();
+        let mut now = false;
+        for event in first
+            .into_iter()
+            .chain(std::iter::from_fn(|| rx.try_recv().ok()).take(waiting))
+        {
+            match apply_event(&mut tui_state, event, shutdown) {
+                Redraw::Later => changed = true,
+                Redraw::Now => now = true,
+                Redraw::Quit => return Ok(()),
+            }
+        }
+
+        if now || last_draw.elapsed() >= interval {
+            let resized = terminal.size()? != size;
+            let counting_down = tui_state.tabs.iter().any(|tab| tab.restart_at.is_some());
+            // uptime is shown for current tab, or for all of them in overview
+            let running = if tui_state.overview.is_some() {
+                tui_state.tabs.iter().any(|tab| tab.started_at.is_some())
+            } else {
+                tui_state
+                    .tabs
+                    .get(tui_state.index)
+                    .is_some_and(|tab| tab.started_at.is_some())
+            };
+            if now || changed || resized || counting_down || running {
+                draw_screen(&mut tui_state, &mut terminal)?;
+                size = terminal.size()?;
+                changed = false;
+            }
+            last_draw = Instant::now();
+        }
     }
 }
 
-// updates tui data based on input or times out silently in 250 ms
-fn update_tui_state(tui_state: &mut TuiState, rx: &Receiver<TuiEvent>) {
-    if let Ok(Ok(event)) = task::block_on(timeout(Duration::from_millis(250), rx.recv())) {
-        match event {
-            TuiEvent::TabListChanged(titles) => {
-                tui_state.tabs = titles
-                    .iter()
-                    .map(|title| TabState::build(title.clone()))
-                    .collect()
+// updates tui data based on event
+fn apply_event(tui_state: &mut TuiState, event: TuiEvent, shutdown: &Shutdown) -> Redraw {
+    match event {
+        TuiEvent::TabListChanged(tabs) => {
+            tui_state.tabs = tabs
+                .into_iter()
+                .map(|(title, scrollback)| TabState::build(title, scrollback))
+                .collect()
+        }
+        TuiEvent::CommandStarted(idx, pid, run_info) => {
+            tui_state.tabs[idx].process_started(pid, run_info);
+            tui_state.tabs[idx].add_message(Severity::System, String::from("Command Started"))
+        }
+        TuiEvent::NewStdoutMessage(idx, message) => {
+            tui_state.tabs[idx].add_message(Severity::Info, message)
+        }
+        TuiEvent::NewStderrMessage(idx, message) => {
+            tui_state.tabs[idx].add_message(Severity::Error, message)
+        }
+        TuiEvent::CommandEnded(idx, exit_status, crashed) => {
+            tui_state.tabs[idx].process_ended(exit_status, crashed);
+            tui_state.tabs[idx].add_message(Severity::System, String::from("Command ended"))
+        }
+        TuiEvent::NewSystemMessage(idx, message) => {
+            tui_state.tabs[idx].add_message(Severity::System, message)
+        }
+        TuiEvent::ReadinessChanged(idx, ready) => {
+            tui_state.tabs[idx].starting = !ready;
+            tui_state.tabs[idx].add_message(Severity::System, readiness_message(ready))
+        }
+        TuiEvent::RestartScheduled(idx, delay) => {
+            tui_state.tabs[idx].state = ProcessState::BackingOff;
+            tui_state.tabs[idx].restart_at = Some(Instant::now() + delay);
+            tui_state.tabs[idx].add_message(Severity::System, restart_message(delay))
+        }
+        TuiEvent::StateChanged(idx, state) => tui_state.tabs[idx].set_state(state),
+        TuiEvent::ResourcesSampled(idx, usage) => tui_state.tabs[idx].add_usage(usage),
+        TuiEvent::Input(key) => {
+            handle_key(tui_state, key, shutdown);
+            return Redraw::Now;
+        }
+        TuiEvent::Exit => return Redraw::Quit,
+    }
+    Redraw::Later
+}
+
+// switches tabs, scrolls output of current tab, opens prompts or overview, or quits. While prompt
+// or overview is open keys edit its text or select its rows instead
+fn handle_key(tui_state: &mut TuiState, key: Key, shutdown: &Shutdown) {
+    if key == Key::Ctrl('c') || (key == Key::Char('q') && tui_state.prompt.is_none()) {
+        shutdown.request();
+    } else if tui_state.prompt.is_some() {
+        handle_prompt_key(tui_state, key);
+    } else if tui_state.overview.is_some() {
+        match key {
+            Key::Up => tui_state.select_row(true),
+            Key::Down => tui_state.select_row(false),
+            Key::Char('\n') => tui_state.jump_to_selected(),
+            Key::Esc | Key::Char('o') => tui_state.overview = None,
+            _ => {}
+        }
+    } else {
+        match key {
+            Key::Right => tui_state.next(),
+            Key::Left => tui_state.previous(),
+            Key::Char('o') => tui_state.open_overview(),
+            Key::Char('/') => {
+                if let Some(tab) = tui_state.current() {
+                    tab.search = None;
+                    tab.search_match = None;
+                    tui_state.prompt = Some(Prompt::new(PromptKind::Search, String::new()));
+                }
             }
-            TuiEvent::CommandStarted(idx) => {
-                tui_state.tabs[idx].add_message(Severity::System, String::from("Command Started"))
+            Key::Char('&') => {
+                if let Some(tab) = tui_state.current() {
+                    let text = tab.filter.as_ref().map_or("", |filter| filter.text());
+                    tui_state.prompt = Some(Prompt::new(PromptKind::Filter, text.to_owned()));
+                }
             }
-            TuiEvent::NewStdoutMessage(idx, message) => {
-                tui_state.tabs[idx].add_message(Severity::Info, message)
+            key => scroll(tui_state, key),
+        }
+    }
+}
+
+// scrolls output of current tab
+fn scroll(tui_state: &mut TuiState, key: Key) {
+    let page = tui_state.page.max(1);
+    let tab = match tui_state.current() {
+        Some(tab) => tab,
+        None => return,
+    };
+    match key {
+        Key::Up => tab.scroll_up(1, page),
+        Key::Down => tab.scroll_down(1, page),
+        Key::PageUp => tab.scroll_up(page, page),
+        Key::PageDown => tab.scroll_down(page, page),
+        Key::Home => tab.scroll_up(tab.content.len(), page),
+        Key::End => tab.scroll_to_bottom(),
+        Key::Char('f') => tab.toggle_follow(),
+        Key::Char('n') => {
+            tab.find(true, false, page);
+        }
+        Key::Char('N') => {
+            tab.find(false, false, page);
+        }
+        _ => {}
+    }
+}
+
+// edits text of open prompt. Search is updated with every key, filter once it is confirmed
+fn handle_prompt_key(tui_state: &mut TuiState, key: Key) {
+    let page = tui_state.page.max(1);
+    let (prompt, tab) = match (
+        &mut tui_state.prompt,
+        tui_state.tabs.get_mut(tui_state.index),
+    ) {
+        (Some(prompt), Some(tab)) => (prompt, tab),
+        _ => return,
+    };
+    match key {
+        Key::Char('\n') => match prompt.kind {
+            PromptKind::Search => tui_state.prompt = None,
+            PromptKind::Filter => match Filter::parse(&prompt.text) {
+                Ok(filter) => {
+                    tab.set_filter(filter);
+                    tui_state.prompt = None;
+                }
+                Err(err) => prompt.error = Some(err.to_string()),
+            },
+        },
+        Key::Esc => {
+            if prompt.kind == PromptKind::Search {
+                tab.search = None;
+                tab.search_match = None;
             }
-            TuiEvent::NewStderrMessage(idx, message) => {
-                tui_state.tabs[idx].add_message(Severity::Error, message)
+            tui_state.prompt = None;
+        }
+        Key::Backspace | Key::Char(_) => {
+            match key {
+                Key::Char(c) => prompt.text.push(c),
+                _ => {
+                    prompt.text.pop();
+                }
             }
-            TuiEvent::CommandEnded(idx) => {
-                tui_state.tabs[idx].add_message(Severity::System, String::from("Command ended"))
+            prompt.error = None;
+            if prompt.kind == PromptKind::Search {
+                tab.search = create_search(&prompt.text);
+                tab.find(true, true, page);
             }
-            TuiEvent::Input(key) => match key {
-                Key::Right => tui_state.next(),
-                Key::Left => tui_state.previous(),
-                _ => {}
-            },
         }
+        _ => {}
+    }
+}
+
+// color of tab title and status bar, so failed commands stand out
+fn state_color(tab: &TabState) -> Color {
+    if tab.failed() {
+        return Color::Red;
+    }
+    match tab.state {
+        ProcessState::Running if tab.starting => Color::Yellow,
+        ProcessState::Running => Color::Cyan,
+        ProcessState::BackingOff => Color::Yellow,
+        ProcessState::Finished => Color::Green,
+        ProcessState::Pending | ProcessState::Stopped | ProcessState::GivenUp => Color::Gray,
     }
 }
 
-// creates tabs on top of screen
-fn create_tabs(tui_state: &TuiState) -> Tabs {
+// creates tabs on top of screen. Selected tab is reversed, keeping color of its state
+fn create_tabs(tui_state: &TuiState) -> Tabs<'_> {
     let titles = tui_state
         .tabs
         .iter()
-        .map(|t| Spans::from(Span::styled(&t.title, Style::default().fg(Color::Cyan))))
+        .map(|t| {
+            Spans::from(Span::styled(
+                t.status_title(),
+                Style::default().fg(state_color(t)),
+            ))
+        })
         .collect();
     Tabs::new(titles)
-        .block(Block::default().borders(Borders::ALL).title("Commands"))
+        .block(
+            Block::default().borders(Borders::ALL).title(
+                "Commands (q to quit, o for overview, f to follow, / to search, & to filter)",
+            ),
+        )
         .select(tui_state.index)
         .style(Style::default().fg(Color::Cyan))
-        .highlight_style(
-            Style::default()
-                .add_modifier(Modifier::BOLD)
-                .fg(Color::Magenta),
-        )
+        .highlight_style(Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED))
+}
+
+// draws status of current command's process under the tabs, with history of its cpu and memory
+// usage below it
+fn draw_status<B: Backend>(f: &mut Frame<B>, tab: &TabState, area: Rect) {
+    let lines = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
+        .split(area);
+    let status = Paragraph::new(Span::styled(
+        format!(" {}", tab.status_line()),
+        Style::default().fg(state_color(tab)),
+    ));
+    f.render_widget(status, lines[0]);
+
+    let halves = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(lines[1]);
+    let usage = tab.current_usage();
+    let cpu: Vec<u64> = tab
+        .usage
+        .iter()
+        .map(|usage| usage.cpu.round() as u64)
+        .collect();
+    draw_usage(
+        f,
+        halves[0],
+        format!(
+            " CPU {}",
+            usage.map_or_else(|| String::from("-"), |usage| format!("{:.1}%", usage.cpu))
+        ),
+        &cpu,
+        // one fully used core fills the sparkline, more cores keep scale of the busiest sample
+        Some(cpu.iter().copied().max().unwrap_or(0).max(100)),
+    );
+    let memory: Vec<u64> = tab.usage.iter().map(|usage| usage.memory).collect();
+    draw_usage(
+        f,
+        halves[1],
+        format!(
+            " RSS {}",
+            usage.map_or_else(|| String::from("-"), |usage| format_memory(usage.memory))
+        ),
+        &memory,
+        None,
+    );
 }
 
-// draws output in the bottom of the screen
-fn create_output(tui_state: &TuiState) -> List {
-    let messages: Vec<ListItem> = tui_state.tabs[tui_state.index]
-        .content
+// draws label followed by sparkline of most recent values that fit
+fn draw_usage<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    label: String,
+    data: &[u64],
+    max: Option<u64>,
+) {
+    let parts = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(16), Constraint::Min(0)].as_ref())
+        .split(area);
+    f.render_widget(Paragraph::new(label), parts[0]);
+    let recent = &data[data.len().saturating_sub(usize::from(parts[1].width))..];
+    let mut sparkline = Sparkline::default()
+        .data(recent)
+        .style(Style::default().fg(Color::Green));
+    if let Some(max) = max {
+        sparkline = sparkline.max(max);
+    }
+    f.render_widget(sparkline, parts[1]);
+}
+
+// draws table summarizing all commands in place of output, one row per tab
+fn create_overview(tui_state: &TuiState) -> Table<'_> {
+    let header = Row::new(vec![
+        "Command",
+        "State",
+        "Uptime",
+        "Restarts",
+        "Last crash",
+        "CPU",
+        "Memory",
+    ])
+    .style(Style::default().add_modifier(Modifier::BOLD));
+    let rows: Vec<Row> = tui_state
+        .tabs
         .iter()
+        .map(|tab| {
+            Row::new(vec![
+                Cell::from(tab.title.as_str()),
+                Cell::from(tab.state.as_str()),
+                Cell::from(tab.uptime().unwrap_or_else(|| String::from("-"))),
+                Cell::from(tab.restarts().to_string()),
+                Cell::from(tab.last_crash.map_or_else(
+                    || String::from("-"),
+                    |last_crash| last_crash.format("%Y-%m-%d %H:%M:%S").to_string(),
+                )),
+                Cell::from(
+                    tab.current_usage()
+                        .map_or_else(|| String::from("-"), |usage| format!("{:.1}%", usage.cpu)),
+                ),
+                Cell::from(
+                    tab.current_usage()
+                        .map_or_else(|| String::from("-"), |usage| format_memory(usage.memory)),
+                ),
+            ])
+            .style(Style::default().fg(state_color(tab)))
+        })
+        .collect();
+    Table::new(rows)
+        .header(header)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Overview (Enter to open, Esc to close)"),
+        )
+        .widths(&OVERVIEW_WIDTHS)
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+}
+
+// draws output in the bottom of the screen, newest messages at the bottom. Search matches are
+// highlighted. Tab list may not have arrived yet on first draw
+fn create_output(tui_state: &TuiState) -> List<'_> {
+    let tab = match tui_state.tabs.get(tui_state.index) {
+        Some(tab) => tab,
+        None => return List::new(Vec::new()).block(Block::default().borders(Borders::ALL)),
+    };
+    let messages: Vec<ListItem> = tab
+        .visible(tui_state.page)
+        .into_iter()
         .rev()
-        .map(|(severity, text)| match severity {
-            Severity::Info => ListItem::new(Span::styled(text, Style::default().fg(Color::White))),
-            Severity::Error => {
-                ListItem::new(Span::styled(text, Style::default().fg(Color::Magenta)))
-            }
-            Severity::System => ListItem::new(Span::styled(text, Style::default().fg(Color::Cyan))),
+        .map(|index| {
+            let (severity, text) = &tab.content[index];
+            let color = match severity {
+                Severity::Info => Color::White,
+                Severity::Error => Color::Magenta,
+                Severity::System => Color::Cyan,
+            };
+            let highlight = if tab.is_search_match(index) {
+                Color::Yellow
+            } else {
+                Color::Gray
+            };
+            ListItem::new(highlight_matches(
+                text,
+                tab.search.as_ref(),
+                Style::default().fg(color),
+                Style::default().fg(Color::Black).bg(highlight),
+            ))
         })
         .collect();
 
     List::new(messages)
-        .block(Block::default().borders(Borders::ALL).title("Output"))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Output ({})", tab.position(tui_state.page))),
+        )
         .start_corner(Corner::BottomLeft)
 }
+
+// splits text into spans, styling parts matching search differently
+fn highlight_matches<'a>(
+    text: &'a str,
+    search: Option<&Regex>,
+    style: Style,
+    highlight: Style,
+) -> Spans<'a> {
+    let search = match search {
+        Some(search) => search,
+        None => return Spans::from(Span::styled(text, style)),
+    };
+    let mut spans = Vec::new();
+    let mut end = 0;
+    for found in search.find_iter(text) {
+        spans.push(Span::styled(&text[end..found.start()], style));
+        spans.push(Span::styled(found.as_str(), highlight));
+        end = found.end();
+    }
+    spans.push(Span::styled(&text[end..], style));
+    Spans::from(spans)
+}
+
+// draws prompt line below output
+fn create_prompt(prompt: &Prompt) -> Paragraph<'_> {
+    let label = match prompt.kind {
+        PromptKind::Search => "/",
+        PromptKind::Filter => "filter (regex, :out, :err or :sys): ",
+    };
+    let mut spans = vec![
+        Span::styled(label, Style::default().fg(Color::Cyan)),
+        Span::styled(prompt.text.as_str(), Style::default().fg(Color::White)),
+    ];
+    if let Some(error) = &prompt.error {
+        spans.push(Span::styled(
+            format!("  {}", error.lines().last().unwrap_or_default()),
+            Style::default().fg(Color::Magenta),
+        ));
+    }
+    Paragraph::new(Spans::from(spans))
+}
@@ -15,12 +15,25 @@ You should have received a copy of the GNU General Public License
 along with this program. If not, see <http://www.gnu.org/licenses/>.
 */
 
-use std::collections::VecDeque;
+use std::{
+    collections::VecDeque,
+    ops::Range,
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
+    time::{Duration, Instant},
+};
 
+use chrono::{DateTime, Utc};
+use regex::Regex;
 use termion::event::Key;
 
+use crate::{resources::ResourceUsage, run_command::RunInfo};
+
+// number of resource usage samples kept for sparklines
+const USAGE_HISTORY: usize = 300usize;
+
 // severity of messages for display purposes
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub(crate) enum Severity {
     // information, messages from stdout
     Info,
@@ -32,14 +45,50 @@ pub(crate) enum Severity {
     System,
 }
 
+// supervision state of a command, as shown in the interface
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ProcessState {
+    // waiting for previous commands or dependencies
+    Pending,
+
+    // process is running
+    Running,
+
+    // waiting before process is restarted
+    BackingOff,
+
+    // stopped through control socket or by shutdown
+    Stopped,
+
+    // done according to its mode
+    Finished,
+
+    // crash limit was reached and runner gave up on the command
+    GivenUp,
+}
+
+impl ProcessState {
+    // name of the state shown in status bar
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ProcessState::Pending => "pending",
+            ProcessState::Running => "running",
+            ProcessState::BackingOff => "backing off",
+            ProcessState::Stopped => "stopped",
+            ProcessState::Finished => "finished",
+            ProcessState::GivenUp => "given up",
+        }
+    }
+}
+
 // All possible events that should have tui react to
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub(crate) enum TuiEvent {
-    // tab list for interface (1 command / tab)
-    TabListChanged(Vec<String>),
+    // tab list for interface (1 command / tab), with title and scrollback size of each tab
+    TabListChanged(Vec<(String, usize)>),
 
-    // command at given id started
-    CommandStarted(usize),
+    // process of command at given id started with given pid
+    CommandStarted(usize, u32, RunInfo),
 
     // new stdout at given id
     NewStdoutMessage(usize, String),
@@ -47,11 +96,86 @@ pub(crate) enum TuiEvent {
     // new stderr at given id
     NewStderrMessage(usize, String),
 
-    // command with the given id ended
-    CommandEnded(usize),
+    // process of command with the given id ended with given exit status, and whether it crashed
+    CommandEnded(usize, ExitStatus, bool),
+
+    // runner generated message at given id
+    NewSystemMessage(usize, String),
+
+    // command at given id is waiting to become ready (false) or became ready (true)
+    ReadinessChanged(usize, bool),
+
+    // command at given id will be restarted after given delay
+    RestartScheduled(usize, Duration),
+
+    // supervision of command at given id ended in given state, until it is started again
+    StateChanged(usize, ProcessState),
+
+    // resource usage of running process of command at given id was sampled
+    ResourcesSampled(usize, ResourceUsage),
 
     // user pressed a key
     Input(Key),
+
+    // runner finished shutting down, tui should restore terminal and quit
+    Exit,
+}
+
+// message shown when readiness of a command changes
+pub(crate) fn readiness_message(ready: bool) -> String {
+    String::from(if ready {
+        "Command ready"
+    } else {
+        "Waiting for command to become ready"
+    })
+}
+
+// message shown when restart of a command is delayed
+pub(crate) fn restart_message(delay: Duration) -> String {
+    format!("Restarting in {:.1}s", delay.as_secs_f64())
+}
+
+// describes how process ended
+pub(crate) fn exit_message(exit_status: ExitStatus) -> String {
+    match (exit_status.code(), exit_status.signal()) {
+        (Some(code), _) => format!("exit code {}", code),
+        (None, Some(signal)) => format!("signal {}", signal),
+        (None, None) => String::from("unknown"),
+    }
+}
+
+// formats uptime with its two most significant units
+pub(crate) fn format_uptime(uptime: Duration) -> String {
+    let seconds = uptime.as_secs();
+    let (minutes, hours, days) = (seconds / 60, seconds / 3600, seconds / 86400);
+    if days > 0 {
+        format!("{}d {:02}h", days, hours % 24)
+    } else if hours > 0 {
+        format!("{}h {:02}m", hours, minutes % 60)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+// formats memory size with binary units
+pub(crate) fn format_memory(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    if unit == "B" {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, unit)
+    }
 }
 
 // Entire state of Tui
@@ -61,17 +185,37 @@ pub(crate) struct TuiState {
 
     // currently active tab
     pub(crate) index: usize,
+
+    // number of lines output pane shows, known after first draw
+    pub(crate) page: usize,
+
+    // open search or filter prompt
+    pub(crate) prompt: Option<Prompt>,
+
+    // selected row of overview of all commands, while it is shown instead of output
+    pub(crate) overview: Option<usize>,
 }
 
 impl TuiState {
-    // create entire state from list of commands
-    pub(crate) fn build(titles: Vec<String>) -> TuiState {
+    // create entire state from list of commands with their scrollback sizes
+    pub(crate) fn build(tabs: Vec<(String, usize)>) -> TuiState {
         TuiState {
-            tabs: titles.into_iter().map(TabState::build).collect(),
+            tabs: tabs
+                .into_iter()
+                .map(|(title, scrollback)| TabState::build(title, scrollback))
+                .collect(),
             index: 0,
+            page: 0,
+            prompt: None,
+            overview: None,
         }
     }
 
+    // currently active tab, if tab list arrived already
+    pub(crate) fn current(&mut self) -> Option<&mut TabState> {
+        self.tabs.get_mut(self.index)
+    }
+
     // switch to next tab
     pub(crate) fn next(&mut self) {
         self.index = (self.index + 1) % self.tabs.len();
@@ -85,6 +229,110 @@ impl TuiState {
             self.index = self.tabs.len() - 1;
         }
     }
+
+    // shows overview with row of current tab selected
+    pub(crate) fn open_overview(&mut self) {
+        if !self.tabs.is_empty() {
+            self.overview = Some(self.index);
+        }
+    }
+
+    // moves overview selection by one row up or down, stopping at first and last row
+    pub(crate) fn select_row(&mut self, up: bool) {
+        if let Some(selected) = &mut self.overview {
+            *selected = if up {
+                selected.saturating_sub(1)
+            } else {
+                (*selected + 1).min(self.tabs.len().saturating_sub(1))
+            };
+        }
+    }
+
+    // closes overview, switching to tab of selected row
+    pub(crate) fn jump_to_selected(&mut self) {
+        if let Some(selected) = self.overview.take() {
+            self.index = selected;
+        }
+    }
+}
+
+// text typed at the bottom of the screen
+pub(crate) struct Prompt {
+    // what the text is used for
+    pub(crate) kind: PromptKind,
+
+    // text typed so far
+    pub(crate) text: String,
+
+    // why confirmed text was not accepted
+    pub(crate) error: Option<String>,
+}
+
+// use of prompt text
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PromptKind {
+    // incremental search in current tab
+    Search,
+
+    // filter of lines shown in current tab
+    Filter,
+}
+
+impl Prompt {
+    // opens empty prompt, or prompt starting with given text
+    pub(crate) fn new(kind: PromptKind, text: String) -> Prompt {
+        Prompt {
+            kind,
+            text,
+            error: None,
+        }
+    }
+}
+
+// lines shown in a tab, either matching a pattern or of a single severity
+#[derive(Debug)]
+pub(crate) enum Filter {
+    Pattern(Regex),
+    Severity(Severity, String),
+}
+
+impl Filter {
+    // parses filter typed by user, ":out", ":err" and ":sys" select severity, anything else is a
+    // pattern. Returns None for empty text, which removes the filter
+    pub(crate) fn parse(text: &str) -> Result<Option<Filter>, regex::Error> {
+        let severity = match text {
+            "" => return Ok(None),
+            ":out" => Severity::Info,
+            ":err" => Severity::Error,
+            ":sys" => Severity::System,
+            pattern => return Regex::new(pattern).map(|regex| Some(Filter::Pattern(regex))),
+        };
+        Ok(Some(Filter::Severity(severity, text.to_owned())))
+    }
+
+    // whether message is shown
+    pub(crate) fn matches(&self, severity: Severity, text: &str) -> bool {
+        match self {
+            Filter::Pattern(regex) => regex.is_match(text),
+            Filter::Severity(shown, _) => *shown == severity,
+        }
+    }
+
+    // filter as it was typed
+    pub(crate) fn text(&self) -> &str {
+        match self {
+            Filter::Pattern(regex) => regex.as_str(),
+            Filter::Severity(_, text) => text,
+        }
+    }
+}
+
+// creates case insensitive search for given text, None for empty text
+pub(crate) fn create_search(text: &str) -> Option<Regex> {
+    if text.is_empty() {
+        return None;
+    }
+    Regex::new(&format!("(?i){}", regex::escape(text))).ok()
 }
 
 // Info about single tab
@@ -94,22 +342,577 @@ pub(crate) struct TabState {
 
     // messages to display in tab
     pub(crate) content: VecDeque<(Severity, String)>,
+
+    // number of messages kept
+    pub(crate) scrollback: usize,
+
+    // number of messages cleaned so far. Together with index in content identifies a message
+    pub(crate) removed: usize,
+
+    // number of newest shown messages below the bottom of output pane
+    pub(crate) scroll: usize,
+
+    // whether output pane is pinned to newest message
+    pub(crate) follow: bool,
+
+    // only messages matching filter are shown
+    pub(crate) filter: Option<Filter>,
+
+    // highlighted search
+    pub(crate) search: Option<Regex>,
+
+    // message search moved to last, as removed + index in content
+    pub(crate) search_match: Option<usize>,
+
+    // whether running process is still waiting to become ready
+    pub(crate) starting: bool,
+
+    // time of next restart, if restart is delayed
+    pub(crate) restart_at: Option<Instant>,
+
+    // supervision state of the command
+    pub(crate) state: ProcessState,
+
+    // pid of running process
+    pub(crate) pid: Option<u32>,
+
+    // when running process was started
+    pub(crate) started_at: Option<Instant>,
+
+    // number of processes started so far, not counting backup strategy actions
+    pub(crate) starts: u64,
+
+    // pid of running backup strategy action
+    pub(crate) action_pid: Option<u32>,
+
+    // number of backup strategy actions started so far
+    pub(crate) actions: u64,
+
+    // crashes counted towards crash limit of current backup strategy stage
+    pub(crate) recent_crashes: usize,
+
+    // how last process ended
+    pub(crate) last_exit: Option<ExitStatus>,
+
+    // when last crashed process ended
+    pub(crate) last_crash: Option<DateTime<Utc>>,
+
+    // recent resource usage of running process, oldest first
+    pub(crate) usage: VecDeque<ResourceUsage>,
 }
 
 impl TabState {
     // build tab from title
-    pub(crate) fn build(title: String) -> TabState {
+    pub(crate) fn build(title: String, scrollback: usize) -> TabState {
         TabState {
             title,
             content: VecDeque::new(),
+            scrollback,
+            removed: 0,
+            scroll: 0,
+            follow: true,
+            filter: None,
+            search: None,
+            search_match: None,
+            starting: false,
+            restart_at: None,
+            state: ProcessState::Pending,
+            pid: None,
+            started_at: None,
+            starts: 0,
+            action_pid: None,
+            actions: 0,
+            recent_crashes: 0,
+            last_exit: None,
+            last_crash: None,
+            usage: VecDeque::new(),
         }
     }
 
-    // adds message to the list, cleans old ones
+    // records start of a process. Backup strategy actions are tracked apart from the supervised
+    // process, so they do not replace its status
+    pub(crate) fn process_started(&mut self, pid: u32, run_info: RunInfo) {
+        if run_info.action {
+            self.action_pid = Some(pid);
+            self.actions += 1;
+            return;
+        }
+        self.state = ProcessState::Running;
+        self.pid = Some(pid);
+        self.started_at = Some(Instant::now());
+        self.starts += 1;
+        self.recent_crashes = run_info.recent_crashes;
+        self.restart_at = None;
+        self.usage.clear();
+    }
+
+    // records resource usage sample of running process
+    pub(crate) fn add_usage(&mut self, usage: ResourceUsage) {
+        if self.action_pid.is_some() {
+            return;
+        }
+        self.usage.push_back(usage);
+        while self.usage.len() > USAGE_HISTORY {
+            self.usage.pop_front();
+        }
+    }
+
+    // latest resource usage of running process
+    pub(crate) fn current_usage(&self) -> Option<&ResourceUsage> {
+        self.pid.and(self.usage.back())
+    }
+
+    // records end of a process. Actions never run together with supervised process, so running
+    // action is the one that ended
+    pub(crate) fn process_ended(&mut self, exit_status: ExitStatus, crashed: bool) {
+        if self.action_pid.take().is_some() {
+            return;
+        }
+        self.pid = None;
+        self.started_at = None;
+        self.last_exit = Some(exit_status);
+        if crashed {
+            self.last_crash = Some(Utc::now());
+        }
+        self.starting = false;
+    }
+
+    // number of processes started after the first one
+    pub(crate) fn restarts(&self) -> u64 {
+        self.starts.saturating_sub(1)
+    }
+
+    // uptime of running process
+    pub(crate) fn uptime(&self) -> Option<String> {
+        self.started_at
+            .map(|started_at| format_uptime(started_at.elapsed()))
+    }
+
+    // records state supervision of the command ended in
+    pub(crate) fn set_state(&mut self, state: ProcessState) {
+        self.state = state;
+        self.restart_at = None;
+    }
+
+    // whether command ended badly, because runner gave up on it or its last process failed
+    pub(crate) fn failed(&self) -> bool {
+        self.state == ProcessState::GivenUp
+            || (self.state == ProcessState::Finished
+                && self
+                    .last_exit
+                    .is_some_and(|exit_status| !exit_status.success()))
+    }
+
+    // one line summary of the process shown under the tabs
+    pub(crate) fn status_line(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(pid) = self.pid {
+            parts.push(format!("PID {}", pid));
+        }
+        if let Some(pid) = self.action_pid {
+            parts.push(format!("backup action PID {}", pid));
+        }
+        parts.push(self.state.as_str().to_owned());
+        if let Some(uptime) = self.uptime() {
+            parts.push(format!("up {}", uptime));
+        }
+        if let Some(usage) = self.current_usage() {
+            parts.push(format!(
+                "{} threads, {} open files",
+                usage.threads, usage.open_files
+            ));
+        }
+        parts.push(format!(
+            "restarts {} ({} in crash window)",
+            self.restarts(),
+            self.recent_crashes
+        ));
+        if self.actions > 0 {
+            parts.push(format!("backup actions {}", self.actions));
+        }
+        if let Some(exit_status) = self.last_exit {
+            parts.push(format!("last exit: {}", exit_message(exit_status)));
+        }
+        parts.join(" | ")
+    }
+
+    // tab title with current status of the command and filter
+    pub(crate) fn status_title(&self) -> String {
+        let restart_in = self
+            .restart_at
+            .map(|restart_at| restart_at.saturating_duration_since(Instant::now()))
+            .filter(|restart_in| !restart_in.is_zero());
+        let title = if let Some(restart_in) = restart_in {
+            format!("{} (restart in {}s)", self.title, restart_in.as_secs() + 1)
+        } else if self.starting {
+            format!("{} (starting)", self.title)
+        } else {
+            self.title.clone()
+        };
+        match &self.filter {
+            Some(filter) => format!("{} [{}]", title, filter.text()),
+            None => title,
+        }
+    }
+
+    // adds message to the list, cleans old ones. Output pane that does not follow keeps showing
+    // the same messages
     pub(crate) fn add_message(&mut self, severity: Severity, text: String) {
+        let shown = self
+            .filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(severity, &text));
         self.content.push_back((severity, text));
-        while self.content.len() > 100 {
+        if !self.follow && shown {
+            self.scroll += 1;
+        }
+        while self.content.len() > self.scrollback {
             self.content.pop_front();
+            self.removed += 1;
+        }
+        self.scroll = self.scroll.min(self.content.len());
+    }
+
+    // indices of messages passing filter, oldest first
+    pub(crate) fn shown(&self) -> Vec<usize> {
+        match &self.filter {
+            Some(filter) => self
+                .content
+                .iter()
+                .enumerate()
+                .filter(|(_, (severity, text))| filter.matches(*severity, text))
+                .map(|(index, _)| index)
+                .collect(),
+            None => (0..self.content.len()).collect(),
+        }
+    }
+
+    // shows only messages matching filter, starting from the newest one
+    pub(crate) fn set_filter(&mut self, filter: Option<Filter>) {
+        self.filter = filter;
+        self.scroll = 0;
+    }
+
+    // scrolls towards older messages, stopping once oldest message is at the top of given page.
+    // Output pane stops following
+    pub(crate) fn scroll_up(&mut self, lines: usize, page: usize) {
+        self.follow = false;
+        self.scroll = (self.scroll + lines).min(self.shown().len().saturating_sub(page));
+    }
+
+    // scrolls towards newer messages from what is shown on a page of given size
+    pub(crate) fn scroll_down(&mut self, lines: usize, page: usize) {
+        self.scroll = self
+            .scroll
+            .min(self.shown().len().saturating_sub(page))
+            .saturating_sub(lines);
+    }
+
+    // jumps to newest message and follows new ones
+    pub(crate) fn scroll_to_bottom(&mut self) {
+        self.scroll = 0;
+        self.follow = true;
+    }
+
+    // starts following new messages, or stops following them
+    pub(crate) fn toggle_follow(&mut self) {
+        if self.follow {
+            self.follow = false;
+        } else {
+            self.scroll_to_bottom();
+        }
+    }
+
+    // indices of messages shown on a page of given size, oldest first
+    pub(crate) fn visible(&self, page: usize) -> Vec<usize> {
+        let shown = self.shown();
+        shown[self.visible_range(shown.len(), page)].to_vec()
+    }
+
+    // description of position of output pane, for example "51-100 of 250"
+    pub(crate) fn position(&self, page: usize) -> String {
+        let shown = self.shown().len();
+        let visible = self.visible_range(shown, page);
+        format!(
+            "{}-{} of {}{}",
+            (visible.start + 1).min(visible.end),
+            visible.end,
+            shown,
+            if self.follow { ", following" } else { "" }
+        )
+    }
+
+    // moves to next message matching search, older or newer than last match. Without last match
+    // search starts at the bottom of the page when going to older messages and at its top when
+    // going to newer ones. Last match itself is only found again if included, so search
+    // continues from it while it is being typed. Returns whether a match was found
+    pub(crate) fn find(&mut self, older: bool, include_last: bool, page: usize) -> bool {
+        let search = match &self.search {
+            Some(search) => search,
+            None => return false,
+        };
+        let shown = self.shown();
+        let visible = self.visible_range(shown.len(), page);
+        let last = self
+            .search_match
+            .and_then(|id| id.checked_sub(self.removed))
+            .and_then(|index| shown.binary_search(&index).ok());
+        let candidates: Box<dyn Iterator<Item = usize>> = match (last, older) {
+            (Some(last), true) if include_last => Box::new((0..=last).rev()),
+            (Some(last), true) => Box::new((0..last).rev()),
+            (Some(last), false) if include_last => Box::new(last..shown.len()),
+            (Some(last), false) => Box::new(last + 1..shown.len()),
+            (None, true) => Box::new((0..visible.end).rev()),
+            (None, false) => Box::new(visible.start..shown.len()),
+        };
+        let found = candidates
+            .into_iter()
+            .find(|&position| search.is_match(&self.content[shown[position]].1));
+        let position = match found {
+            Some(position) => position,
+            None => return false,
+        };
+        self.search_match = Some(self.removed + shown[position]);
+        if !visible.contains(&position) {
+            self.follow = false;
+            self.scroll = shown.len() - 1 - position;
+        }
+        true
+    }
+
+    // whether message at given index is the last search match
+    pub(crate) fn is_search_match(&self, index: usize) -> bool {
+        self.search_match == Some(self.removed + index)
+    }
+
+    // positions of shown messages on a page of given size. Page stays full when messages shown
+    // at its top were cleaned
+    fn visible_range(&self, shown: usize, page: usize) -> Range<usize> {
+        let end = (shown - self.scroll.min(shown)).max(page.min(shown));
+        end.saturating_sub(page)..end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn tab(lines: usize, scrollback: usize) -> TabState {
+        let mut tab = TabState::build(String::from("server"), scrollback);
+        for line in 0..lines {
+            tab.add_message(Severity::Info, line.to_string());
         }
+        tab
+    }
+
+    #[test]
+    fn test_scrolling() {
+        let mut tab = tab(30, 20);
+        assert_eq!(tab.content.len(), 20);
+        assert_eq!(tab.visible(5), (15..20).collect::<Vec<_>>());
+        assert_eq!(tab.position(5), "16-20 of 20, following");
+
+        tab.scroll_up(3, 5);
+        assert!(!tab.follow);
+        assert_eq!(tab.visible(5), (12..17).collect::<Vec<_>>());
+        // new messages do not move the pane, until they push shown ones out
+        tab.add_message(Severity::Info, String::from("new"));
+        assert_eq!(tab.visible(5), (11..16).collect::<Vec<_>>());
+        assert_eq!(tab.content[15].1, "26");
+
+        tab.scroll_up(100, 5);
+        assert_eq!(tab.visible(5), (0..5).collect::<Vec<_>>());
+        tab.add_message(Severity::Info, String::from("newer"));
+        assert_eq!(tab.visible(5), (0..5).collect::<Vec<_>>());
+        assert_eq!(tab.position(5), "1-5 of 20");
+
+        tab.scroll_down(2, 5);
+        assert_eq!(tab.visible(5), (2..7).collect::<Vec<_>>());
+        tab.scroll_to_bottom();
+        assert_eq!(tab.visible(5), (15..20).collect::<Vec<_>>());
+        tab.toggle_follow();
+        assert!(!tab.follow);
+        tab.toggle_follow();
+        assert!(tab.follow);
+    }
+
+    #[test]
+    fn test_short_content() {
+        let mut tab = tab(3, 20);
+        assert_eq!(tab.visible(5), (0..3).collect::<Vec<_>>());
+        tab.scroll_up(1, 5);
+        assert_eq!(tab.visible(5), (0..3).collect::<Vec<_>>());
+        assert_eq!(tab.position(5), "1-3 of 3");
+        assert_eq!(
+            TabState::build(String::new(), 20).position(5),
+            "0-0 of 0, following"
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut tab = TabState::build(String::from("server"), 100);
+        for line in 0..10 {
+            tab.add_message(Severity::Info, format!("line {}", line));
+            tab.add_message(Severity::Error, format!("error {}", line));
+        }
+        tab.set_filter(Filter::parse(":err").unwrap());
+        assert_eq!(tab.visible(3), vec![15, 17, 19]);
+        assert_eq!(tab.position(3), "8-10 of 10, following");
+        assert_eq!(tab.status_title(), "server [:err]");
+
+        tab.set_filter(Filter::parse("line [0-2]$").unwrap());
+        assert_eq!(tab.visible(5), vec![0, 2, 4]);
+
+        // only shown messages move pane that does not follow
+        tab.set_filter(Filter::parse("line").unwrap());
+        tab.scroll_up(1, 5);
+        tab.add_message(Severity::Error, String::from("error 10"));
+        assert_eq!(tab.scroll, 1);
+        tab.add_message(Severity::Info, String::from("line 10"));
+        assert_eq!(tab.scroll, 2);
+
+        assert!(Filter::parse("(").is_err());
+        tab.set_filter(Filter::parse("").unwrap());
+        assert_eq!(tab.status_title(), "server");
+    }
+
+    #[test]
+    fn test_find() {
+        let mut tab = TabState::build(String::from("server"), 100);
+        for line in 0..20 {
+            let text = if line % 5 == 0 { "Match" } else { "other" };
+            tab.add_message(Severity::Info, format!("{} {}", text, line));
+        }
+        tab.search = create_search("match");
+        assert!(tab.find(true, true, 3));
+        assert!(tab.is_search_match(15));
+        assert!(!tab.follow);
+        assert_eq!(tab.visible(3), vec![13, 14, 15]);
+
+        // visible match does not move the pane
+        assert!(tab.find(true, true, 5));
+        assert!(tab.is_search_match(15));
+        assert_eq!(tab.visible(5), vec![11, 12, 13, 14, 15]);
+        assert!(tab.find(true, false, 5));
+        assert!(tab.is_search_match(10));
+        assert_eq!(tab.visible(5), vec![6, 7, 8, 9, 10]);
+        assert!(tab.find(true, false, 3));
+        assert!(tab.is_search_match(5));
+        assert_eq!(tab.visible(3), vec![3, 4, 5]);
+
+        assert!(tab.find(true, false, 3));
+        assert!(tab.is_search_match(0));
+        assert!(!tab.find(true, false, 3));
+        assert!(tab.find(false, false, 3));
+        assert!(tab.is_search_match(5));
+
+        tab.search = create_search("missing");
+        assert!(!tab.find(true, true, 3));
+        assert!(create_search("").is_none());
+    }
+
+    #[test]
+    fn test_status_line() {
+        let mut tab = tab(0, 10);
+        assert_eq!(
+            tab.status_line(),
+            "pending | restarts 0 (0 in crash window)"
+        );
+
+        let run_info = RunInfo {
+            restart: 1,
+            stage: 0,
+            recent_crashes: 1,
+            action: false,
+        };
+        tab.process_started(42, RunInfo::default());
+        tab.process_ended(ExitStatus::from_raw(9), true);
+        tab.process_started(43, run_info);
+        assert!(tab.last_crash.is_some());
+        assert_eq!(
+            tab.status_line(),
+            "PID 43 | running | up 0s | restarts 1 (1 in crash window) | last exit: signal 9"
+        );
+        assert!(!tab.failed());
+
+        tab.process_ended(ExitStatus::from_raw(3 << 8), false);
+        tab.set_state(ProcessState::Finished);
+        assert_eq!(
+            tab.status_line(),
+            "finished | restarts 1 (1 in crash window) | last exit: exit code 3"
+        );
+        assert!(tab.failed());
+
+        tab.process_started(44, run_info);
+        tab.add_usage(ResourceUsage {
+            time: Utc::now(),
+            cpu: 1.0,
+            memory: 1024,
+            threads: 2,
+            open_files: 5,
+        });
+        assert_eq!(
+            tab.status_line(),
+            "PID 44 | running | up 0s | 2 threads, 5 open files | restarts 2 (1 in crash window) \
+             | last exit: exit code 3"
+        );
+        tab.process_ended(ExitStatus::from_raw(0), false);
+        assert!(tab.current_usage().is_none());
+
+        // backup strategy actions do not count as restarts or replace last exit
+        let action = RunInfo {
+            action: true,
+            ..RunInfo::default()
+        };
+        tab.process_started(45, action);
+        assert_eq!(
+            tab.status_line(),
+            "backup action PID 45 | running | restarts 2 (1 in crash window) | backup actions 1 \
+             | last exit: exit code 0"
+        );
+        tab.process_ended(ExitStatus::from_raw(3 << 8), true);
+        assert_eq!(
+            tab.status_line(),
+            "running | restarts 2 (1 in crash window) | backup actions 1 | last exit: exit code 0"
+        );
+
+        assert_eq!(format_memory(512), "512 B");
+        assert_eq!(format_memory(1536), "1.5 KB");
+        assert_eq!(format_memory(300 * 1024 * 1024), "300.0 MB");
+        assert_eq!(format_uptime(Duration::from_secs(59)), "59s");
+        assert_eq!(format_uptime(Duration::from_secs(61)), "1m 01s");
+        assert_eq!(format_uptime(Duration::from_secs(3 * 3600 + 120)), "3h 02m");
+        assert_eq!(
+            format_uptime(Duration::from_secs(2 * 86400 + 3600)),
+            "2d 01h"
+        );
+    }
+
+    #[test]
+    fn test_overview() {
+        let mut tui_state = TuiState::build(Vec::new());
+        tui_state.open_overview();
+        assert_eq!(tui_state.overview, None);
+
+        tui_state = TuiState::build(vec![
+            (String::from("db"), 10),
+            (String::from("server"), 10),
+            (String::from("worker"), 10),
+        ]);
+        tui_state.next();
+        tui_state.open_overview();
+        assert_eq!(tui_state.overview, Some(1));
+        tui_state.select_row(false);
+        tui_state.select_row(false);
+        assert_eq!(tui_state.overview, Some(2));
+        tui_state.select_row(true);
+        tui_state.select_row(true);
+        tui_state.select_row(true);
+        assert_eq!(tui_state.overview, Some(0));
+        tui_state.jump_to_selected();
+        assert_eq!(tui_state.overview, None);
+        assert_eq!(tui_state.index, 0);
     }
 }
@@ -0,0 +1,166 @@
+/*
+This file is part of the Everdream Runner (https://gitlab.com/everdream/runner).
+Copyright (c) 2021 Everdream.
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, version 3.
+
+This program is distributed in the hope that it will be useful, but
+WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the GNU
+General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program. If not, see <http://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+
+use async_std::channel::{Sender, TrySendError};
+
+use crate::{runner_error::Result, tui_state::TuiEvent};
+
+// passes events on to the interface. Output lines of lossy sender never wait for it, lines that do
+// not fit into full channel are dropped and counted per command instead. Count is reported before
+// next line of the command that fits, or before any other event. Other events, and all events of
+// sender that is not lossy, wait for room, so they are never lost
+pub(crate) struct UiSender {
+    tx: Sender<TuiEvent>,
+    lossy: bool,
+    dropped: BTreeMap<usize, u64>,
+}
+
+impl UiSender {
+    // creates sender for given interface channel. Only interactive interface should be lossy, as
+    // headless output is expected to be complete
+    pub(crate) fn new(tx: Sender<TuiEvent>, lossy: bool) -> UiSender {
+        UiSender {
+            tx,
+            lossy,
+            dropped: BTreeMap::new(),
+        }
+    }
+
+    // sends event, dropping output line if sender is lossy and channel is full
+    pub(crate) async fn send(&mut self, event: TuiEvent) -> Result<()> {
+        let id = match &event {
+            TuiEvent::NewStdoutMessage(id, _) | TuiEvent::NewStderrMessage(id, _) if self.lossy => {
+                *id
+            }
+            _ => {
+                self.report_dropped().await?;
+                self.tx.send(event).await?;
+                return Ok(());
+            }
+        };
+        if let Some(&dropped) = self.dropped.get(&id) {
+            if !self.try_send(id, dropped_message(id, dropped))? {
+                return Ok(());
+            }
+            self.dropped.remove(&id);
+        }
+        self.try_send(id, event)?;
+        Ok(())
+    }
+
+    // reports all dropped lines, waiting for room if needed
+    pub(crate) async fn report_dropped(&mut self) -> Result<()> {
+        for (id, dropped) in std::mem::take(&mut self.dropped) {
+            self.tx.send(dropped_message(id, dropped)).await?;
+        }
+        Ok(())
+    }
+
+    // sends event if there is room, otherwise counts dropped line of given command. Returns
+    // whether event was sent
+    fn try_send(&mut self, id: usize, event: TuiEvent) -> Result<bool> {
+        match self.tx.try_send(event) {
+            Ok(()) => Ok(true),
+            Err(TrySendError::Full(_)) => {
+                *self.dropped.entry(id).or_insert(0) += 1;
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+// system message with number of dropped lines
+fn dropped_message(id: usize, dropped: u64) -> TuiEvent {
+    TuiEvent::NewSystemMessage(id, format!("{} lines dropped", dropped))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+
+    use async_std::{channel, task};
+
+    use super::*;
+
+    fn receive(rx: &channel::Receiver<TuiEvent>) -> Vec<TuiEvent> {
+        std::iter::from_fn(|| rx.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn test_send() {
+        let (tx, rx) = channel::bounded(3);
+        let mut sender = UiSender::new(tx, true);
+        let line = |id: usize, line: &str| TuiEvent::NewStdoutMessage(id, String::from(line));
+        task::block_on(async {
+            for index in 0..5 {
+                sender.send(line(0, &index.to_string())).await.unwrap();
+            }
+            sender.send(line(1, "other")).await.unwrap();
+            assert_eq!(receive(&rx), vec![line(0, "0"), line(0, "1"), line(0, "2")]);
+
+            // count is reported before next line of the same command that fits
+            for index in 5..8 {
+                sender.send(line(0, &index.to_string())).await.unwrap();
+            }
+            assert_eq!(
+                receive(&rx),
+                vec![
+                    TuiEvent::NewSystemMessage(0, String::from("2 lines dropped")),
+                    line(0, "5"),
+                    line(0, "6"),
+                ]
+            );
+
+            // and before any other event, which is never dropped
+            sender
+                .send(TuiEvent::CommandEnded(0, ExitStatus::from_raw(0), false))
+                .await
+                .unwrap();
+            assert_eq!(
+                receive(&rx),
+                vec![
+                    TuiEvent::NewSystemMessage(0, String::from("1 lines dropped")),
+                    TuiEvent::NewSystemMessage(1, String::from("1 lines dropped")),
+                    TuiEvent::CommandEnded(0, ExitStatus::from_raw(0), false),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_send_lossless() {
+        let (tx, rx) = channel::bounded(1);
+        let mut sender = UiSender::new(tx, false);
+        let line = |index: usize| TuiEvent::NewStdoutMessage(0, index.to_string());
+        let sending = task::spawn(async move {
+            for index in 0..5 {
+                sender.send(line(index)).await.unwrap();
+            }
+        });
+        task::block_on(async {
+            // lines wait for room instead of being dropped
+            for index in 0..5 {
+                assert_eq!(rx.recv().await.unwrap(), line(index));
+            }
+            sending.await;
+        });
+    }
+}